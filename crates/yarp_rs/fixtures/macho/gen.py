@@ -0,0 +1,187 @@
+"""
+Writes the Mach-O fixtures used by the parser tests, so they can be regenerated without a macos toolchain.
+The dylibs only have a header and load commands, there is no code in them.
+
+    lib/libb.dylib    arm64, LC_ID_DYLIB @rpath/libb.dylib
+    lib/liba.dylib    fat (x86_64 + arm64)
+                      arm64:  LC_LOAD_DYLIB @rpath/libb.dylib, LC_RPATH @loader_path
+                      x86_64: LC_LOAD_DYLIB @loader_path/libb.dylib
+                      both:   LC_LOAD_DYLIB /usr/lib/libSystem.B.dylib
+    lib/lib{c,d,e}.dylib  arm64, LC_ID_DYLIB @rpath/lib{c,d,e}.dylib
+                      libe also loads @rpath/libkinds.dylib, which loads it upward
+    lib/libkinds.dylib    arm64, LC_RPATH @loader_path and a dylib load command of every kind
+                      LC_LOAD_DYLIB @rpath/libb.dylib, LC_REEXPORT_DYLIB @rpath/libc.dylib,
+                      LC_LAZY_LOAD_DYLIB @rpath/libd.dylib, LC_LOAD_UPWARD_DYLIB @rpath/libe.dylib,
+                      LC_LOAD_WEAK_DYLIB @rpath/libmissing.dylib (which does not exist)
+    chain/libparent.dylib       arm64, LC_RPATH @loader_path/deps, LC_LOAD_DYLIB @rpath/libchild.dylib
+    chain/deps/libchild.dylib   arm64, no LC_RPATH, LC_LOAD_DYLIB @rpath/libleaf.dylib
+                                which only resolves with the rpath inherited from libparent
+    chain/deps/libleaf.dylib    arm64
+
+Run from this directory: python3 gen.py
+"""
+
+import os
+import struct
+
+MH_MAGIC_64 = 0xFEEDFACF
+FAT_MAGIC = 0xCAFEBABE
+MH_DYLIB = 6
+FLAGS = 0x1 | 0x4 | 0x80  # MH_NOUNDEFS | MH_DYLDLINK | MH_TWOLEVEL
+
+LC_SEGMENT_64 = 0x19
+LC_ID_DYLIB = 0xD
+LC_LOAD_DYLIB = 0xC
+LC_LOAD_WEAK_DYLIB = 0x80000018
+LC_REEXPORT_DYLIB = 0x8000001F
+LC_LAZY_LOAD_DYLIB = 0x20
+LC_LOAD_UPWARD_DYLIB = 0x80000023
+LC_RPATH = 0x8000001C
+
+CPU_X86_64 = (0x01000007, 3)
+CPU_ARM64 = (0x0100000C, 0)
+
+PAGE = 0x4000
+
+
+def padded(s: str, header: int) -> bytes:
+    raw = s.encode() + b"\0"
+    size = (header + len(raw) + 7) // 8 * 8
+    return raw + b"\0" * (size - header - len(raw))
+
+
+def dylib_cmd(cmd: int, name: str) -> bytes:
+    name = padded(name, 24)
+    return struct.pack("<IIIIII", cmd, 24 + len(name), 24, 2, 0x10000, 0x10000) + name
+
+
+def rpath_cmd(path: str) -> bytes:
+    path = padded(path, 12)
+    return struct.pack("<III", LC_RPATH, 12 + len(path), 12) + path
+
+
+def text_segment() -> bytes:
+    return struct.pack(
+        "<II16sQQQQIIII", LC_SEGMENT_64, 72, b"__TEXT", 0, PAGE, 0, PAGE, 5, 5, 0, 0
+    )
+
+
+def macho(cpu, cmds: list) -> bytes:
+    cmds = [text_segment()] + cmds
+    body = b"".join(cmds)
+    header = struct.pack(
+        "<IiiIIIII", MH_MAGIC_64, cpu[0], cpu[1], MH_DYLIB, len(cmds), len(body), FLAGS, 0
+    )
+    data = header + body
+    return data + b"\0" * (PAGE - len(data))
+
+
+def fat(slices: list) -> bytes:
+    header = struct.pack(">II", FAT_MAGIC, len(slices))
+    offset = PAGE
+    archs, data = b"", b""
+    for cpu, s in slices:
+        archs += struct.pack(">iiIII", cpu[0], cpu[1], offset, len(s), 14)
+        data += s
+        offset += len(s)
+    head = header + archs
+    return head + b"\0" * (PAGE - len(head)) + data
+
+
+def write(path: str, data: bytes):
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, "wb") as f:
+        f.write(data)
+
+
+system = "/usr/lib/libSystem.B.dylib"
+write("lib/libb.dylib", macho(CPU_ARM64, [dylib_cmd(LC_ID_DYLIB, "@rpath/libb.dylib"), dylib_cmd(LC_LOAD_DYLIB, system)]))
+write(
+    "lib/liba.dylib",
+    fat(
+        [
+            (
+                CPU_X86_64,
+                macho(
+                    CPU_X86_64,
+                    [
+                        dylib_cmd(LC_ID_DYLIB, "@rpath/liba.dylib"),
+                        dylib_cmd(LC_LOAD_DYLIB, "@loader_path/libb.dylib"),
+                        dylib_cmd(LC_LOAD_DYLIB, system),
+                    ],
+                ),
+            ),
+            (
+                CPU_ARM64,
+                macho(
+                    CPU_ARM64,
+                    [
+                        dylib_cmd(LC_ID_DYLIB, "@rpath/liba.dylib"),
+                        dylib_cmd(LC_LOAD_DYLIB, "@rpath/libb.dylib"),
+                        dylib_cmd(LC_LOAD_DYLIB, system),
+                        rpath_cmd("@loader_path"),
+                    ],
+                ),
+            ),
+        ]
+    ),
+)
+for name in ["libc", "libd"]:
+    write(
+        f"lib/{name}.dylib",
+        macho(CPU_ARM64, [dylib_cmd(LC_ID_DYLIB, f"@rpath/{name}.dylib"), dylib_cmd(LC_LOAD_DYLIB, system)]),
+    )
+write(
+    "lib/libe.dylib",
+    macho(
+        CPU_ARM64,
+        [
+            dylib_cmd(LC_ID_DYLIB, "@rpath/libe.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, "@rpath/libkinds.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, system),
+        ],
+    ),
+)
+write(
+    "lib/libkinds.dylib",
+    macho(
+        CPU_ARM64,
+        [
+            dylib_cmd(LC_ID_DYLIB, "@rpath/libkinds.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, "@rpath/libb.dylib"),
+            dylib_cmd(LC_REEXPORT_DYLIB, "@rpath/libc.dylib"),
+            dylib_cmd(LC_LAZY_LOAD_DYLIB, "@rpath/libd.dylib"),
+            dylib_cmd(LC_LOAD_UPWARD_DYLIB, "@rpath/libe.dylib"),
+            dylib_cmd(LC_LOAD_WEAK_DYLIB, "@rpath/libmissing.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, system),
+            rpath_cmd("@loader_path"),
+        ],
+    ),
+)
+write(
+    "chain/libparent.dylib",
+    macho(
+        CPU_ARM64,
+        [
+            dylib_cmd(LC_ID_DYLIB, "@rpath/libparent.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, "@rpath/libchild.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, system),
+            rpath_cmd("@loader_path/deps"),
+        ],
+    ),
+)
+write(
+    "chain/deps/libchild.dylib",
+    macho(
+        CPU_ARM64,
+        [
+            dylib_cmd(LC_ID_DYLIB, "@rpath/libchild.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, "@rpath/libleaf.dylib"),
+            dylib_cmd(LC_LOAD_DYLIB, system),
+        ],
+    ),
+)
+write(
+    "chain/deps/libleaf.dylib",
+    macho(CPU_ARM64, [dylib_cmd(LC_ID_DYLIB, "@rpath/libleaf.dylib"), dylib_cmd(LC_LOAD_DYLIB, system)]),
+)
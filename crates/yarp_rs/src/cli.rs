@@ -0,0 +1,172 @@
+// command line interface for yarp
+// every subcommand gets its own args struct here, the implementation lives in `cmd`
+
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::target::Target;
+
+#[derive(Debug, Parser)]
+#[command(name = "yarp", version, about = "package a python environment into a relocatable dist")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// export the files in a yarp manifest to a dist directory
+    Export(ExportArgs),
+
+    /// check that the environment can run yarp
+    Doctor(DoctorArgs),
+
+    /// work with yarp manifests
+    Manifest(ManifestArgs),
+
+    /// print the dependency graph of a manifest, with where every file would be exported
+    Graph(GraphArgs),
+
+    /// explain why a file is in the dist, using the shortest chains of dependents back to the roots
+    Why(WhyArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// path to the yarp manifest (yarp.json) generated by `yarp.discover`, multiple manifests are merged before exporting
+    #[arg(long = "manifest", required = true, num_args = 1..)]
+    pub manifests: Vec<PathBuf>,
+
+    /// JSON file with include/exclude globs per root, added to the `filters` in the manifest
+    #[arg(long)]
+    pub filters: Option<PathBuf>,
+
+    /// only export the site-packages distributions imported at discovery (`imports` in the manifest), and their requirements
+    #[arg(long)]
+    pub prune: bool,
+
+    /// directory to write the dist to, relative paths are resolved from the current directory
+    #[arg(long, default_value = "dist")]
+    pub out: PathBuf,
+
+    /// remove the output directory if it already exists
+    #[arg(long)]
+    pub force: bool,
+
+    /// only print what would be exported and patched, nothing is written to the output directory
+    #[arg(long)]
+    pub plan: bool,
+
+    /// export shared libraries which depend on each other in a cycle, instead of failing
+    /// the reals of the whole cycle are exported before any of their symlink farms
+    #[arg(long)]
+    pub allow_cycles: bool,
+
+    /// output format of `--plan`
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "plan")]
+    pub plan_format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct GraphArgs {
+    /// path to the yarp manifest (yarp.json) generated by `yarp.discover`, multiple manifests are merged
+    #[arg(long = "manifest", required = true, num_args = 1..)]
+    pub manifests: Vec<PathBuf>,
+
+    /// JSON file with include/exclude globs per root, added to the `filters` in the manifest
+    #[arg(long)]
+    pub filters: Option<PathBuf>,
+
+    /// only keep the site-packages distributions imported at discovery, same as `yarp export --prune`
+    #[arg(long)]
+    pub prune: bool,
+
+    /// platform and architecture to analyse binaries for, like macos-aarch64 or linux-x86_64
+    /// defaults to `target` in the manifest, then the host
+    #[arg(long)]
+    pub target: Option<Target>,
+
+    /// only print this file and everything it depends on, say an extension and its shared library closure
+    #[arg(long)]
+    pub from: Option<PathBuf>,
+
+    /// dist directory used for the reals and destination paths of every node, nothing is written to it
+    #[arg(long, default_value = "dist")]
+    pub out: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct WhyArgs {
+    /// the file to explain, relative paths are resolved from the current directory
+    pub path: PathBuf,
+
+    /// path to the yarp manifest (yarp.json) generated by `yarp.discover`, multiple manifests are merged
+    #[arg(long = "manifest", required = true, num_args = 1..)]
+    pub manifests: Vec<PathBuf>,
+
+    /// JSON file with include/exclude globs per root, added to the `filters` in the manifest
+    #[arg(long)]
+    pub filters: Option<PathBuf>,
+
+    /// only keep the site-packages distributions imported at discovery, same as `yarp export --prune`
+    #[arg(long)]
+    pub prune: bool,
+
+    /// platform and architecture to analyse binaries for, like macos-aarch64 or linux-x86_64
+    /// defaults to `target` in the manifest, then the host
+    #[arg(long)]
+    pub target: Option<Target>,
+
+    /// maximum number of chains to print
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// check that all external tools needed for the current platform are installed, and are recent enough
+    #[arg(long)]
+    pub tools: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ManifestArgs {
+    #[command(subcommand)]
+    pub command: ManifestCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ManifestCommand {
+    /// print the JSON Schema of the current manifest version
+    Schema,
+
+    /// merge manifests of multiple discovery runs, all of them must use the same python
+    Merge(MergeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct MergeArgs {
+    /// manifests to merge, loads/libs/skip/env are unioned in the given order
+    #[arg(required = true, num_args = 2..)]
+    pub manifests: Vec<PathBuf>,
+
+    /// path to write the merged manifest to
+    #[arg(short, long)]
+    pub out: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
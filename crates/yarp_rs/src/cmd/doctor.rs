@@ -0,0 +1,39 @@
+// `yarp doctor`: check that the current environment can run yarp
+
+use std::env::consts::OS;
+
+use anyhow::{Result, bail};
+
+use crate::{
+    cli::DoctorArgs,
+    preflight::{ToolStatus, check_tools, ensure_all_ok},
+};
+
+pub fn run(args: &DoctorArgs) -> Result<()> {
+    if !args.tools {
+        bail!("nothing to check, pass --tools");
+    }
+    let checks = check_tools(OS);
+    if checks.is_empty() {
+        println!("no external tools are needed on os={}", OS);
+        return Ok(());
+    }
+    for check in &checks {
+        let status = match &check.status {
+            ToolStatus::Ok { version: Some(v) } => format!("ok ({})", v),
+            ToolStatus::Ok { version: None } => "ok".to_string(),
+            ToolStatus::Missing => "missing".to_string(),
+            ToolStatus::TooOld { version } => format!(
+                "too old ({}, need at least {})",
+                version,
+                check
+                    .tool
+                    .minimum
+                    .map(|(major, minor)| format!("{}.{}", major, minor))
+                    .unwrap_or_default()
+            ),
+        };
+        println!("{:<20} {:<32} {}", check.tool.program, status, check.tool.purpose);
+    }
+    ensure_all_ok(&checks)
+}
@@ -0,0 +1,172 @@
+// `yarp export`: build the graph from a manifest and move every node to dist
+
+use std::{
+    collections::BTreeMap,
+    env::{self, consts::OS},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Error, Result, bail};
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::{
+    cli::{ExportArgs, OutputFormat},
+    error::Failures,
+    gather::{NodeFactory, build_graph_from_manifest},
+    graph::FileGraph,
+    manifest::{read_filters, read_manifests},
+    node::Node,
+    paths::normalize_path,
+    preflight::{preflight, preflight_search},
+    target::Target,
+    pkg::{
+        bootstrap::write_bootstrap_script,
+        move_cycle_to_dist, move_to_dist,
+        paths::ExportedFileTree,
+        plan::{plan_node, write_plan_json, write_plan_text},
+        staging::StagingDist,
+    },
+};
+
+pub fn run(args: &ExportArgs) -> Result<()> {
+    let mut manifest = read_manifests(&args.manifests)?;
+    if let Some(filters) = &args.filters {
+        manifest.filters.extend(read_filters(filters)?);
+    }
+    // patching runs the target's tools on the host, a manifest of another platform can only be inspected (`yarp graph`)
+    let target = Target::resolve(None, manifest.target)?;
+    if target.os != Target::host()?.os {
+        bail!(
+            "cannot export a manifest discovered on {} from a {} host, run the export on the target platform",
+            target.os,
+            OS
+        );
+    }
+    let cwd = env::current_dir().context("failed in getting current directory")?;
+    let dist = normalize_path(&cwd.join(&args.out));
+    if args.plan {
+        // nothing is patched, only gather's ldconfig/ldd have to be usable
+        preflight_search(OS)?;
+        let (graph, _, report) =
+            build_graph_from_manifest(&manifest, &cwd, args.prune, target).context("failed in building graph")?;
+        report.write_summary(&mut std::io::stderr().lock())?;
+        return print_plan(&graph, &dist, args.plan_format, args.allow_cycles);
+    }
+    // gather shells out to ldconfig/ldd and patching needs patchelf/install_name_tool, check them before the long graph build
+    preflight(OS)?;
+    // fail fast, before the long graph build
+    if dist.exists() && !args.force {
+        bail!(
+            "output directory already exists, pass --force to overwrite it, path={}",
+            dist.display()
+        );
+    }
+
+    let (graph, path_components, report) =
+        build_graph_from_manifest(&manifest, &cwd, args.prune, target).context("failed in building graph")?;
+    report.write_summary(&mut std::io::stderr().lock())?;
+
+    // nodes are exported to a staging directory, which is swapped in only if everything succeeds
+    let staging = StagingDist::new(&dist)?;
+    move_all_nodes(&graph, staging.path(), args.allow_cycles)?;
+    write_bootstrap_script(staging.path(), &path_components, &manifest.python.sys.version)
+        .context("failed in writing bootstrap script")?;
+    staging.commit(args.force)
+}
+
+fn print_plan(
+    graph: &FileGraph<NodeFactory>,
+    dist: &PathBuf,
+    format: OutputFormat,
+    allow_cycles: bool,
+) -> Result<()> {
+    let nodes: Vec<Node> = if allow_cycles {
+        graph.component_levels().into_iter().flatten().flatten().collect()
+    } else {
+        graph.toposort()?.collect()
+    };
+    let mut plans = Vec::new();
+    for node in nodes {
+        let deps = graph.get_node_dependencies(&node);
+        plans.push(plan_node(&node, &deps, dist)?);
+    }
+    let mut out = std::io::stdout().lock();
+    match format {
+        OutputFormat::Text => write_plan_text(&plans, &mut out),
+        OutputFormat::Json => write_plan_json(&plans, &mut out),
+    }
+}
+
+fn move_all_nodes(graph: &FileGraph<NodeFactory>, dist: &PathBuf, allow_cycles: bool) -> Result<()> {
+    info!("exporting files to dist");
+    let total = graph.len();
+    let done = AtomicUsize::new(0);
+    let levels = if allow_cycles {
+        graph.component_levels()
+    } else {
+        graph
+            .levels()?
+            .into_iter()
+            .map(|level| level.into_iter().map(|n| vec![n]).collect())
+            .collect()
+    };
+    // a node's dependencies are all in earlier levels, their reals exist before its symlink farm is made
+    for components in levels {
+        let (level, cycles): (Vec<Vec<Node>>, Vec<Vec<Node>>) =
+            components.into_iter().partition(|c| !graph.is_cycle(c));
+        let level: Vec<Node> = level.into_iter().flatten().collect();
+        let mut errors: Vec<Error> = group_by_reals(level, dist)
+            .par_iter()
+            .filter_map(|group| {
+                for node in group {
+                    let deps = graph.get_node_dependencies(node);
+                    if let Err(e) = move_to_dist(node, &deps, dist) {
+                        return Some(e);
+                    }
+                    let i = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if total / 10 != 0 && i % (total / 10) == 0 {
+                        info!("exported {}/{} files", i, total);
+                    }
+                }
+                None
+            })
+            .collect();
+        // cycles are rare, they are exported serially after the rest of the level so they can't race with identical files
+        for cycle in cycles {
+            let paths: Vec<String> = cycle.iter().map(|n| n.path.display().to_string()).collect();
+            warn!("exporting dependency cycle: {}", paths.join(" -> "));
+            let nodes: Vec<(Node, Vec<Node>)> = cycle
+                .into_iter()
+                .map(|n| {
+                    let deps = graph.get_node_dependencies(&n);
+                    (n, deps)
+                })
+                .collect();
+            match move_cycle_to_dist(&nodes, dist) {
+                Ok(()) => {
+                    done.fetch_add(nodes.len(), Ordering::Relaxed);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(Error::new(Failures {
+                context: "failed in exporting files to dist".to_string(),
+                errors,
+            }));
+        }
+    }
+    Ok(())
+}
+
+// identical files (same sha) share their reals and symlink farm, they are exported one after the other in the same task
+fn group_by_reals(level: Vec<Node>, dist: &PathBuf) -> Vec<Vec<Node>> {
+    let mut groups: BTreeMap<PathBuf, Vec<Node>> = BTreeMap::new();
+    for node in level {
+        let key = node.pkg.reals(&node, dist).unwrap_or_else(|| node.path.clone());
+        groups.entry(key).or_default().push(node);
+    }
+    groups.into_values().collect()
+}
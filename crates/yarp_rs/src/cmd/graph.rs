@@ -0,0 +1,35 @@
+// `yarp graph`: build the graph from a manifest and print it as DOT or JSON, nothing is exported
+
+use std::env;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    cli::{GraphArgs, GraphFormat},
+    gather::build_graph_from_manifest,
+    manifest::{read_filters, read_manifests},
+    paths::normalize_path,
+    target::Target,
+    pkg::inspect::{view_graph, write_graph_dot, write_graph_json},
+};
+
+pub fn run(args: &GraphArgs) -> Result<()> {
+    let mut manifest = read_manifests(&args.manifests)?;
+    if let Some(filters) = &args.filters {
+        manifest.filters.extend(read_filters(filters)?);
+    }
+    let target = Target::resolve(args.target, manifest.target)?;
+    let cwd = env::current_dir().context("failed in getting current directory")?;
+    let dist = normalize_path(&cwd.join(&args.out));
+    let (graph, _, report) =
+        build_graph_from_manifest(&manifest, &cwd, args.prune, target).context("failed in building graph")?;
+    report.write_summary(&mut std::io::stderr().lock())?;
+
+    let from = args.from.as_ref().map(|p| normalize_path(&cwd.join(p)));
+    let view = view_graph(&graph, &dist, from.as_ref())?;
+    let mut out = std::io::stdout().lock();
+    match args.format {
+        GraphFormat::Dot => write_graph_dot(&view, &mut out),
+        GraphFormat::Json => write_graph_json(&view, &mut out),
+    }
+}
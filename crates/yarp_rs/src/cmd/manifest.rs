@@ -0,0 +1,38 @@
+// `yarp manifest`: utilities for yarp manifests
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::{
+    cli::{ManifestArgs, ManifestCommand, MergeArgs},
+    manifest::{manifest_schema, read_manifests},
+};
+
+pub fn run(args: &ManifestArgs) -> Result<()> {
+    match &args.command {
+        ManifestCommand::Schema => {
+            let schema = serde_json::to_string_pretty(&manifest_schema())
+                .context("failed in serializing manifest schema")?;
+            println!("{}", schema);
+            Ok(())
+        }
+        ManifestCommand::Merge(args) => merge(args),
+    }
+}
+
+fn merge(args: &MergeArgs) -> Result<()> {
+    let merged = read_manifests(&args.manifests)?;
+    let contents =
+        serde_json::to_string_pretty(&merged).context("failed in serializing merged manifest")?;
+    std::fs::write(&args.out, contents).with_context(|| {
+        anyhow!(
+            "failed in writing merged manifest, path={}",
+            args.out.display()
+        )
+    })?;
+    println!(
+        "merged {} manifests into path={}",
+        args.manifests.len(),
+        args.out.display()
+    );
+    Ok(())
+}
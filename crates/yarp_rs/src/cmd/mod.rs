@@ -0,0 +1,21 @@
+// implementation of all subcommands exposed in `cli`
+
+use anyhow::Result;
+
+use crate::cli::{Cli, Command};
+
+mod doctor;
+mod export;
+mod graph;
+mod manifest;
+mod why;
+
+pub fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Export(args) => export::run(&args),
+        Command::Doctor(args) => doctor::run(&args),
+        Command::Manifest(args) => manifest::run(&args),
+        Command::Graph(args) => graph::run(&args),
+        Command::Why(args) => why::run(&args),
+    }
+}
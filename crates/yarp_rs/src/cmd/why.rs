@@ -0,0 +1,99 @@
+// `yarp why <path>`: the shortest chains of dependents from a file back to the roots of the graph
+// roots are what the graph was built from: the python executable, manifest loads and libs, and every walked file
+
+use std::{collections::HashSet, env, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    cli::WhyArgs,
+    gather::build_graph_from_manifest,
+    manifest::{YarpManifest, read_filters, read_manifests},
+    node::{Node, Pkg},
+    paths::normalize_path,
+    target::Target,
+};
+
+pub fn run(args: &WhyArgs) -> Result<()> {
+    let mut manifest = read_manifests(&args.manifests)?;
+    if let Some(filters) = &args.filters {
+        manifest.filters.extend(read_filters(filters)?);
+    }
+    let target = Target::resolve(args.target, manifest.target)?;
+    let cwd = env::current_dir().context("failed in getting current directory")?;
+    let (graph, _, report) =
+        build_graph_from_manifest(&manifest, &cwd, args.prune, target).context("failed in building graph")?;
+    report.write_summary(&mut std::io::stderr().lock())?;
+
+    let path = normalize_path(&cwd.join(&args.path));
+    let roots = Roots::new(&manifest);
+    let chains = graph.shortest_chains_to_roots(&path, |n| roots.reason(n).is_some(), args.limit)?;
+    if chains.is_empty() {
+        println!("{} is in the graph, but no root depends on it", path.display());
+        return Ok(());
+    }
+    for (i, chain) in chains.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", chain[0].path.display());
+        for hop in chain.windows(2) {
+            println!("  {}", describe_hop(&hop[0], &hop[1]));
+        }
+        let root = chain.last().expect("chain is never empty");
+        println!(
+            "  root: {}",
+            roots.reason(root).expect("chains always end at a root")
+        );
+    }
+    Ok(())
+}
+
+struct Roots {
+    loads: HashSet<PathBuf>,
+    libs: HashSet<PathBuf>,
+}
+
+impl Roots {
+    fn new(manifest: &YarpManifest) -> Self {
+        Self {
+            loads: manifest.loads.iter().map(|l| l.path.clone()).collect(),
+            libs: manifest.libs.iter().map(|l| l.path.clone()).collect(),
+        }
+    }
+
+    fn reason(&self, node: &Node) -> Option<&'static str> {
+        if self.loads.contains(&node.path) {
+            return Some("manifest load");
+        }
+        if self.libs.contains(&node.path) {
+            return Some("manifest lib");
+        }
+        match node.pkg {
+            Pkg::Executable => Some("python executable"),
+            Pkg::SitePackagesPlain { .. } | Pkg::SitePackagesBinary { .. } => Some("site-packages file"),
+            Pkg::PrefixPlain(_) | Pkg::PrefixBinary(_) => Some("stdlib file"),
+            Pkg::ExecPrefixPlain(_) | Pkg::ExecPrefixBinary(_) => Some("lib-dynload file"),
+            Pkg::Binary { .. } | Pkg::BinaryInLDPath { .. } | Pkg::Plain => None,
+        }
+    }
+}
+
+// `dep` is a dependency of `dependent`, say which string asked for it and how it was found
+fn describe_hop(dep: &Node, dependent: &Node) -> String {
+    let needed: Vec<String> = dependent
+        .deps
+        .needed()
+        .into_iter()
+        .filter(|(_, p)| *p == dep.path)
+        .map(|(name, _)| match dependent.deps.resolution(&name) {
+            Some(resolution) => format!("{} ({})", name, resolution.step),
+            None => name,
+        })
+        .collect();
+    if needed.is_empty() {
+        format!("needed by {}", dependent.path.display())
+    } else {
+        format!("needed as {} by {}", needed.join(", "), dependent.path.display())
+    }
+}
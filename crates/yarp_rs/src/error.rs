@@ -0,0 +1,257 @@
+// typed errors surfaced to the user
+// every variant has a stable code, these codes are documented in docs/ERRORS.md and should never be reused
+// errors are still passed around as `anyhow::Error`, the CLI finds these by walking the error chain
+
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
+use anyhow::Error;
+
+#[derive(Debug, Clone)]
+pub enum YarpError {
+    // a DT_NEEDED entry or a load command which we could not find anywhere
+    // `tried` is every candidate path checked, in order
+    UnresolvedDependency { name: String, path: PathBuf, tried: Vec<PathBuf> },
+
+    // a plain file outside all site-packages, we don't know where to put it in dist
+    NotInSitePackages { path: PathBuf },
+
+    // an external tool (patchelf, install_name_tool, codesign, ldconfig, ldd) could not be executed
+    ToolMissing { tool: String },
+
+    // install_name_tool could not fit the new load commands in the mach-o header
+    HeaderPadExhausted { path: PathBuf },
+
+    // the binary could be parsed, but its contents do not make sense
+    CorruptBinary { path: PathBuf, reason: String },
+
+    // the yarp manifest could not be read or does not match the expected schema
+    ManifestInvalid { path: PathBuf, reason: String },
+
+    // an external tool exists, but does not support what we need
+    ToolTooOld { tool: String, version: String, minimum: String },
+
+    // files which depend on each other in a cycle, each path depends on the next one and the last on the first
+    DependencyCycle { paths: Vec<PathBuf> },
+}
+
+impl YarpError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            YarpError::UnresolvedDependency { .. } => "E001",
+            YarpError::NotInSitePackages { .. } => "E002",
+            YarpError::ToolMissing { .. } => "E003",
+            YarpError::HeaderPadExhausted { .. } => "E004",
+            YarpError::CorruptBinary { .. } => "E005",
+            YarpError::ManifestInvalid { .. } => "E006",
+            YarpError::ToolTooOld { .. } => "E007",
+            YarpError::DependencyCycle { .. } => "E008",
+        }
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            YarpError::UnresolvedDependency { .. } => {
+                "a shared library needs a dependency which could not be found using the platform's search rules"
+            }
+            YarpError::NotInSitePackages { .. } => {
+                "a plain (non shared library) file is outside all site-packages, stdlib and lib-dynload directories"
+            }
+            YarpError::ToolMissing { .. } => {
+                "a tool required for patching binaries or searching libraries is not installed"
+            }
+            YarpError::HeaderPadExhausted { .. } => {
+                "the mach-o header does not have enough space left for the rewritten load commands"
+            }
+            YarpError::CorruptBinary { .. } => "a binary contains entries which are invalid",
+            YarpError::ManifestInvalid { .. } => {
+                "the yarp manifest could not be read, or it points to files which do not exist"
+            }
+            YarpError::ToolTooOld { .. } => "an installed tool is older than the version yarp needs",
+            YarpError::DependencyCycle { .. } => {
+                "shared libraries depend on each other in a cycle, so there is no order to export them in"
+            }
+        }
+    }
+
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            YarpError::UnresolvedDependency { .. } => {
+                "make the dependency findable (LD_LIBRARY_PATH/DYLD_LIBRARY_PATH in manifest env, or `libs`), add the library to `optional` if it is fine to be missing, or add it to `skip.libs`"
+            }
+            YarpError::NotInSitePackages { .. } => {
+                "add the directory to sys.path before running discovery, or add it to `skip.prefixes`"
+            }
+            YarpError::ToolMissing { .. } => {
+                "install the tool (patchelf on linux, Xcode command line tools on macos) and make sure it is in PATH, `yarp doctor --tools` lists everything needed"
+            }
+            YarpError::HeaderPadExhausted { .. } => {
+                "relink the library with `-headerpad_max_install_names`, or add it to `skip.libs`"
+            }
+            YarpError::CorruptBinary { .. } => {
+                "reinstall the package providing the binary, or add it to `skip.libs`"
+            }
+            YarpError::ManifestInvalid { .. } => {
+                "regenerate the manifest using `yarp.discover` with the same yarp version"
+            }
+            YarpError::ToolTooOld { .. } => "upgrade the tool, `yarp doctor --tools` shows the versions found",
+            YarpError::DependencyCycle { .. } => {
+                "pass `--allow-cycles` to export all libraries of a cycle together, or add one of them to `skip.libs`"
+            }
+        }
+    }
+
+    /// find the first `YarpError` in the chain of an error
+    pub fn find(e: &Error) -> Option<&YarpError> {
+        e.chain().find_map(|c| c.downcast_ref::<YarpError>())
+    }
+}
+
+impl fmt::Display for YarpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YarpError::UnresolvedDependency { name, path, tried } => {
+                write!(
+                    f,
+                    "failed in finding dependency {} for library at path={}",
+                    name,
+                    path.display()
+                )?;
+                if !tried.is_empty() {
+                    let tried: Vec<String> = tried.iter().map(|p| p.display().to_string()).collect();
+                    write!(f, ", tried: {}", tried.join(", "))?;
+                }
+                Ok(())
+            }
+            YarpError::NotInSitePackages { path } => write!(
+                f,
+                "found a path which is not inside site packages and is not a shared library. Only plain files inside site-packages are allowed, path={}",
+                path.display()
+            ),
+            YarpError::ToolMissing { tool } => write!(f, "could not run `{}`", tool),
+            YarpError::HeaderPadExhausted { path } => write!(
+                f,
+                "no space left in header for new load commands, path={}",
+                path.display()
+            ),
+            YarpError::CorruptBinary { path, reason } => {
+                write!(f, "corrupt binary at path={}: {}", path.display(), reason)
+            }
+            YarpError::ManifestInvalid { path, reason } => {
+                write!(f, "invalid yarp manifest at path={}: {}", path.display(), reason)
+            }
+            YarpError::ToolTooOld {
+                tool,
+                version,
+                minimum,
+            } => write!(f, "`{}` version {} is too old, need at least {}", tool, version, minimum),
+            YarpError::DependencyCycle { paths } => {
+                let mut cycle: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                if let Some(first) = cycle.first().cloned() {
+                    cycle.push(first);
+                }
+                write!(f, "dependency cycle: {}", cycle.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for YarpError {}
+
+/// multiple independent failures, raised when we collect errors instead of stopping at the first one
+#[derive(Debug)]
+pub struct Failures {
+    pub context: String,
+    pub errors: Vec<Error>,
+}
+
+impl fmt::Display for Failures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} errors)", self.context, self.errors.len())
+    }
+}
+
+impl std::error::Error for Failures {}
+
+/// render an error for the CLI, all errors sharing a code are grouped together with their explanation and fix
+pub fn render(e: &Error) -> String {
+    let failures = e.chain().find_map(|c| c.downcast_ref::<Failures>());
+    let errors: Vec<&Error> = match failures {
+        Some(failures) => failures.errors.iter().collect(),
+        None => vec![e],
+    };
+
+    let mut by_code: BTreeMap<&str, (Option<&YarpError>, Vec<&Error>)> = BTreeMap::new();
+    for err in errors {
+        let typed = YarpError::find(err);
+        let code = typed.map(|t| t.code()).unwrap_or("unclassified");
+        let entry = by_code.entry(code).or_insert((typed, Vec::new()));
+        entry.1.push(err);
+    }
+
+    let mut out = String::new();
+    if failures.is_some() {
+        out.push_str(&format!("error: {:#}\n", e));
+    }
+    for (code, (typed, errs)) in by_code {
+        match typed {
+            Some(typed) => {
+                out.push_str(&format!(
+                    "error[{}]: {} ({} occurrences)\n",
+                    code,
+                    typed.explanation(),
+                    errs.len()
+                ));
+                for err in errs {
+                    out.push_str(&format!("  - {:#}\n", err));
+                }
+                out.push_str(&format!("  help: {}\n", typed.suggestion()));
+            }
+            None => {
+                for err in errs {
+                    out.push_str(&format!("error: {:#}\n", err));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use anyhow::{Context, Error, anyhow};
+
+    use super::{Failures, YarpError, render};
+
+    fn unresolved(name: &str) -> Error {
+        Error::new(YarpError::UnresolvedDependency {
+            name: name.to_string(),
+            path: PathBuf::from("/env/lib/libfoo.so"),
+            tried: vec![PathBuf::from("/env/lib/libbar.so")],
+        })
+    }
+
+    #[test]
+    fn test_find_through_context() {
+        let e = Err::<(), Error>(unresolved("libbar.so"))
+            .context("failed in parsing")
+            .unwrap_err();
+        assert_eq!(YarpError::find(&e).map(|t| t.code()), Some("E001"));
+        assert!(YarpError::find(&anyhow!("plain")).is_none());
+    }
+
+    #[test]
+    fn test_render_groups_by_code() {
+        let e = Error::new(Failures {
+            context: "could not gather".to_string(),
+            errors: vec![unresolved("libbar.so"), anyhow!("something else"), unresolved("libbaz.so")],
+        });
+        let rendered = render(&e);
+        assert_eq!(rendered.matches("error[E001]").count(), 1);
+        assert!(rendered.contains("(2 occurrences)"));
+        assert!(rendered.contains("libbar.so"));
+        assert!(rendered.contains("libbaz.so"));
+        assert!(rendered.contains("error: something else"));
+    }
+}
@@ -1,14 +1,14 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::path::PathBuf;
 
 use anyhow::Result;
 
-use crate::node::Node;
+use crate::{node::Node, parse::KnownLibs};
 
 pub trait Factory {
     fn make(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>>;
 
@@ -16,7 +16,7 @@ pub trait Factory {
         &self,
         path: &PathBuf,
         symlinks: &Vec<String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>>;
 
@@ -2,15 +2,16 @@ use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::Result;
 
-use crate::{node::deps::Deps, parse::BinaryParseError, pkg::paths::is_maybe_shared_library};
+use crate::{node::deps::Deps, parse::{BinaryParseError, KnownLibs}, pkg::paths::is_maybe_shared_library, target::Target};
 
 pub fn create_deps(
     path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     extra_search_paths: &Vec<PathBuf>,
+    target: Target,
 ) -> Result<Deps> {
     if is_maybe_shared_library(path) {
         let bin = crate::parse::parse_and_search(
@@ -20,6 +21,7 @@ pub fn create_deps(
             env,
             known_libs,
             extra_search_paths,
+            target,
         );
         match bin {
             Ok(bin) => Ok(Deps::Binary(bin)),
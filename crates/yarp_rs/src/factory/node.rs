@@ -1,15 +1,15 @@
 use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::{Result, bail};
+use anyhow::{Error, Result, bail};
 use log::info;
 
 pub use crate::factory::core::Factory;
 
 use crate::{
-    digest::make_digest, factory::{
+    digest::make_digest, error::YarpError, factory::{
         deps::create_deps,
         pkg::{get_exec_prefix_pkg, get_prefix_pkg, get_site_packages_pkg},
-    }, manifest::{Skip, Version}, node::{deps::Deps, Node, Pkg}, paths::normalize_path, pkg::paths::is_maybe_shared_library, site_pkgs::SitePkgs
+    }, manifest::{Skip, Version}, node::{deps::Deps, Node, Pkg}, parse::KnownLibs, paths::normalize_path, pkg::paths::is_maybe_shared_library, site_pkgs::SitePkgs, target::Target
 };
 
 #[derive(Debug, Clone)]
@@ -20,6 +20,7 @@ pub struct NodeFactory {
     cwd: PathBuf,
     env: HashMap<String, String>,
     skip: Skip,
+    target: Target,
 }
 
 impl NodeFactory {
@@ -30,6 +31,7 @@ impl NodeFactory {
         cwd: PathBuf,
         env: HashMap<String, String>,
         skip: Skip,
+        target: Target,
     ) -> NodeFactory {
         Self {
             site_pkgs,
@@ -38,6 +40,7 @@ impl NodeFactory {
             cwd: cwd,
             env,
             skip,
+            target,
         }
     }
 }
@@ -46,7 +49,7 @@ impl NodeFactory {
     fn create_deps(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Deps> {
         create_deps(
@@ -56,6 +59,7 @@ impl NodeFactory {
             &self.env,
             known_libs,
             extra_search_paths,
+            self.target,
         )
     }
 
@@ -86,12 +90,59 @@ impl NodeFactory {
     }
 }
 
+impl NodeFactory {
+    /// node for a shared library whose dependencies could not be resolved
+    /// it goes to the same place as a resolved one, but it is not parsed (and hence not patched)
+    pub fn make_unresolved(&self, path: &PathBuf) -> Result<Node> {
+        self.make_node(normalize_path(path), Deps::Plain, true)
+    }
+
+    fn make_node(&self, p: PathBuf, deps: Deps, is_shared_library: bool) -> Result<Node> {
+        if p.starts_with(&self.site_pkgs.lib_dynload) {
+            return Node::new(
+                p.clone(),
+                get_exec_prefix_pkg(&p, &self.site_pkgs.lib_dynload, &self.version, is_shared_library)?,
+                deps,
+            );
+        }
+
+        if p.starts_with(&self.site_pkgs.stdlib) {
+            return Node::new(
+                p.clone(),
+                get_prefix_pkg(&p, &self.site_pkgs.stdlib, &self.version, is_shared_library)?,
+                deps,
+            );
+        }
+
+        for (site_pkg, alias) in &self.site_pkgs.site_pkg_by_alias {
+            if p.starts_with(site_pkg) {
+                return Node::new(
+                    p.clone(),
+                    get_site_packages_pkg(&p, site_pkg, alias, &self.version, is_shared_library)?,
+                    deps,
+                );
+            }
+        }
+
+        if !is_shared_library {
+            return Err(Error::new(YarpError::NotInSitePackages { path: p.clone() }));
+        }
+        Node::new(
+            p.clone(),
+            Pkg::Binary {
+                sha: make_digest(&p)?,
+            },
+            deps,
+        )
+    }
+}
+
 impl Factory for NodeFactory {
     fn make_with_symlinks(
         &self,
         path: &PathBuf,
         symlinks: &Vec<String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>> {
         let deps = self.create_deps(&path, known_libs, extra_search_paths)?;
@@ -119,7 +170,7 @@ impl Factory for NodeFactory {
     fn make(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>> {
         let p = normalize_path(path);
@@ -136,52 +187,14 @@ impl Factory for NodeFactory {
 
         let deps = self.create_deps(&p, known_libs, extra_search_paths)?;
         let is_shared_library = deps.is_shared_library();
-        if p.starts_with(&self.site_pkgs.lib_dynload) {
-            return Ok(Some(Node::new(
-                p.clone(),
-                get_exec_prefix_pkg(&p, &self.site_pkgs.lib_dynload, &self.version, is_shared_library)?,
-                deps,
-            )?));
-        }
-
-        if p.starts_with(&self.site_pkgs.stdlib) {
-            return Ok(Some(Node::new(
-                p.clone(),
-                get_prefix_pkg(&p, &self.site_pkgs.stdlib, &self.version, is_shared_library)?,
-                deps,
-            )?));
-        }
-
-        for (site_pkg, alias) in &self.site_pkgs.site_pkg_by_alias {
-            if p.starts_with(site_pkg) {
-                return Ok(Some(Node::new(
-                    p.clone(),
-                    get_site_packages_pkg(&p, site_pkg, alias, &self.version, is_shared_library)?,
-                    deps,
-                )?));
-            }
-        }
-
-        if !is_shared_library {
-            bail!(
-                "found a path which is not inside site packages and is not a shared library. Only plain files inside site-packages are allowed, path={}",
-                p.display()
-            );
-        }
-        Ok(Some(Node::new(
-            p.clone(),
-            Pkg::Binary {
-                sha: make_digest(&p)?,
-            },
-            deps,
-        )?))
+        self.make_node(p, deps, is_shared_library).map(Some)
     }
 
     fn make_py_executable(&self, path: &PathBuf) -> Result<Node> {
         Node::new(
             path.clone(),
             Pkg::Executable,
-            self.create_deps(path, &HashMap::new(), &Vec::new())?,
+            self.create_deps(path, &KnownLibs::default(), &Vec::new())?,
         )
     }
 }
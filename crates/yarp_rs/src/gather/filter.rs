@@ -0,0 +1,207 @@
+// include/exclude globs for files walked in each root (stdlib, lib-dynload and every site-packages)
+// roots are nested (site-packages and lib-dynload are usually inside stdlib), a file is always judged by its innermost root
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{
+    manifest::{FileFilter, Filters},
+    site_pkgs::SitePkgs,
+};
+
+#[derive(Debug)]
+struct CompiledFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+
+    // original globs, indexed the same as the sets, for the summary
+    exclude_globs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct RootFilters {
+    // sorted by depth, deepest first, so that the first root containing a path is its innermost one
+    roots: Vec<(PathBuf, CompiledFilter)>,
+}
+
+impl RootFilters {
+    pub fn new(filters: &Filters, site_pkgs: &SitePkgs) -> Result<Self> {
+        let mut roots = Vec::new();
+        roots.push((site_pkgs.stdlib.clone(), compile(&filters.stdlib)?));
+        roots.push((site_pkgs.lib_dynload.clone(), compile(&filters.lib_dynload)?));
+        for pkg in site_pkgs.site_pkg_by_alias.keys() {
+            let mut filter = filters.site_packages.clone();
+            if let Some(own) = filters.roots.get(pkg) {
+                filter.extend(own.clone());
+            }
+            roots.push((pkg.clone(), compile(&filter)?));
+        }
+        roots.sort_by(|(a, _), (b, _)| {
+            b.components()
+                .count()
+                .cmp(&a.components().count())
+                .then_with(|| a.cmp(b))
+        });
+        Ok(Self { roots })
+    }
+
+    /// `Some(reason)` if the file at `path` should not be exported, `reason` is the glob which excluded it
+    pub fn excluded_by(&self, path: &Path) -> Option<(&Path, String)> {
+        let (root, filter) = self.roots.iter().find(|(root, _)| path.starts_with(root))?;
+        let rel_path = path.strip_prefix(root).ok()?;
+        if !filter.include.is_empty() && !filter.include.is_match(rel_path) {
+            return Some((root, "(not included)".to_string()));
+        }
+        filter
+            .exclude
+            .matches(rel_path)
+            .first()
+            .map(|&i| (root.as_path(), filter.exclude_globs[i].clone()))
+    }
+}
+
+fn compile(filter: &FileFilter) -> Result<CompiledFilter> {
+    Ok(CompiledFilter {
+        include: build_set(&filter.include)?,
+        exclude: build_set(&filter.exclude)?,
+        exclude_globs: filter.exclude.clone(),
+    })
+}
+
+fn build_set(globs: &Vec<String>) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for g in globs {
+        builder.add(Glob::new(g).with_context(|| anyhow!("invalid glob: {}", g))?);
+    }
+    builder
+        .build()
+        .with_context(|| anyhow!("failed in building glob set for globs={:?}", globs))
+}
+
+/// what was excluded from each root, reported once the graph is built
+#[derive(Debug, Default)]
+pub struct ExcludedFiles {
+    by_root: BTreeMap<PathBuf, RootExclusions>,
+
+    // nested roots are walked twice (site-packages is walked again inside stdlib), count every file once
+    seen: HashSet<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct RootExclusions {
+    files: usize,
+    bytes: u64,
+    files_by_glob: BTreeMap<String, usize>,
+}
+
+impl ExcludedFiles {
+    pub fn add(&mut self, root: &Path, glob: String, path: &Path) {
+        if !self.seen.insert(path.to_path_buf()) {
+            return;
+        }
+        let entry = self.by_root.entry(root.to_path_buf()).or_default();
+        entry.files += 1;
+        entry.bytes += path.metadata().map(|m| m.len()).unwrap_or(0);
+        *entry.files_by_glob.entry(glob).or_default() += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_root.is_empty()
+    }
+
+    pub fn write_summary(&self, out: &mut impl Write) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let files: usize = self.by_root.values().map(|r| r.files).sum();
+        let bytes: u64 = self.by_root.values().map(|r| r.bytes).sum();
+        writeln!(out, "excluded {} files ({}) using filters:", files, human_size(bytes))?;
+        for (root, excluded) in &self.by_root {
+            writeln!(
+                out,
+                "  {}: {} files ({})",
+                root.display(),
+                excluded.files,
+                human_size(excluded.bytes)
+            )?;
+            for (glob, count) in &excluded.files_by_glob {
+                writeln!(out, "    {}: {} files", glob, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= MB {
+        format!("{:.1} MB", bytes as f64 / MB)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use crate::{
+        manifest::{FileFilter, Filters},
+        site_pkgs::SitePkgs,
+    };
+
+    use super::RootFilters;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> FileFilter {
+        FileFilter {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_innermost_root_wins() {
+        let stdlib = PathBuf::from("/env/lib/python3.12");
+        let site_pkg = stdlib.join("site-packages");
+        let site_pkgs = SitePkgs {
+            resolved: vec![],
+            site_pkg_by_alias: HashMap::from([(site_pkg.clone(), "abc".to_string())]),
+            lib_dynload: stdlib.join("lib-dynload"),
+            stdlib: stdlib.clone(),
+            comps: vec![],
+        };
+        let filters = Filters {
+            stdlib: filter(&[], &["idlelib/**", "**/tests/**"]),
+            lib_dynload: filter(&["*.so"], &[]),
+            site_packages: filter(&[], &["**/*.pyi"]),
+            roots: HashMap::from([(site_pkg.clone(), filter(&[], &["numpy/tests/**"]))]),
+        };
+        let filters = RootFilters::new(&filters, &site_pkgs).unwrap();
+
+        let reason = |p: &str| filters.excluded_by(&PathBuf::from(p)).map(|(_, g)| g);
+        assert_eq!(reason("/env/lib/python3.12/idlelib/x.py"), Some("idlelib/**".to_string()));
+        assert_eq!(reason("/env/lib/python3.12/os.py"), None);
+        // stdlib globs do not apply to site-packages
+        assert_eq!(reason("/env/lib/python3.12/site-packages/foo/tests/a.py"), None);
+        assert_eq!(
+            reason("/env/lib/python3.12/site-packages/numpy/tests/a.py"),
+            Some("numpy/tests/**".to_string())
+        );
+        assert_eq!(
+            reason("/env/lib/python3.12/site-packages/foo/a.pyi"),
+            Some("**/*.pyi".to_string())
+        );
+        assert_eq!(
+            reason("/env/lib/python3.12/lib-dynload/README"),
+            Some("(not included)".to_string())
+        );
+        assert_eq!(reason("/env/lib/python3.12/lib-dynload/_csv.so"), None);
+        assert_eq!(reason("/elsewhere/a.py"), None);
+    }
+}
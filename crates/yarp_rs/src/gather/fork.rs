@@ -0,0 +1,204 @@
+// identical binaries share their reals and symlink farm, both are named by the sha of the file
+// a binary vendored in two wheels can resolve its dependencies to different libraries in each, one farm can't link both
+// such copies are forked: each set of resolved dependencies gets a sha of its own, derived from the content and the dependencies
+// forking a dependency changes the dependencies of its dependents, so this is repeated till nothing changes
+
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use anyhow::Result;
+use blake3::Hasher;
+use log::info;
+
+use crate::{factory::Factory, graph::FileGraph};
+
+/// a copy of a binary exported with its own reals and symlink farm
+#[derive(Debug, Clone)]
+pub struct ForkedReals {
+    pub path: PathBuf,
+
+    // sha of the file
+    pub sha: String,
+
+    // names its reals and symlink farm
+    pub forked: String,
+}
+
+// (name in the symlink farm, sha of the dependency) for every dependency with reals
+type DepSet = Vec<(String, String)>;
+
+// a binary as it is linked in dist
+#[derive(Debug)]
+struct Linked {
+    sha: String,
+
+    // name in the symlink farm, and the path of every dependency with reals
+    deps: Vec<(String, PathBuf)>,
+}
+
+/// give copies of a binary which resolve to different dependencies their own sha in the graph
+/// runs before SONAME renames, dependencies are linked in the farm with their file name
+pub fn fork_conflicting_reals<T: Factory>(g: &mut FileGraph<T>) -> Vec<ForkedReals> {
+    let mut linked = BTreeMap::new();
+    for node in g.iter_nodes() {
+        let sha = match node.pkg.sha() {
+            Some(sha) => sha.to_string(),
+            None => continue,
+        };
+        let deps = g
+            .get_node_dependencies(node)
+            .into_iter()
+            .filter(|d| d.pkg.sha().is_some())
+            .filter_map(|d| {
+                let name = d.path.file_name()?.to_string_lossy().to_string();
+                Some((name, d.path))
+            })
+            .collect();
+        linked.insert(node.path.clone(), Linked { sha, deps });
+    }
+
+    let forks = plan_forks(&linked);
+    for f in &forks {
+        info!(
+            "forking reals of path={}, its dependencies differ from identical copies, sha={} forked={}",
+            f.path.display(),
+            f.sha,
+            f.forked
+        );
+        if let Some(node) = g.get_node_by_path_mut(&f.path) {
+            node.pkg.set_sha(f.forked.clone());
+        }
+    }
+    forks
+}
+
+fn plan_forks(linked: &BTreeMap<PathBuf, Linked>) -> Vec<ForkedReals> {
+    let mut shas: BTreeMap<&PathBuf, String> = linked.iter().map(|(p, l)| (p, l.sha.clone())).collect();
+    loop {
+        // sha to the dependency sets of the binaries with it
+        let mut sets: BTreeMap<String, BTreeMap<DepSet, Vec<&PathBuf>>> = BTreeMap::new();
+        for (path, l) in linked {
+            let mut set: DepSet = l
+                .deps
+                .iter()
+                .filter_map(|(name, dep)| shas.get(dep).map(|sha| (name.clone(), sha.clone())))
+                .collect();
+            set.sort();
+            sets.entry(shas[path].clone())
+                .or_default()
+                .entry(set)
+                .or_default()
+                .push(path);
+        }
+
+        let mut changed = false;
+        for (sha, by_set) in sets {
+            if by_set.len() < 2 {
+                continue;
+            }
+            changed = true;
+            for (set, paths) in by_set {
+                let forked = fork_sha(&sha, &set);
+                for path in paths {
+                    shas.insert(path, forked.clone());
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    linked
+        .iter()
+        .filter(|(path, l)| shas[path] != l.sha)
+        .map(|(path, l)| ForkedReals {
+            path: path.clone(),
+            sha: l.sha.clone(),
+            forked: shas[path].clone(),
+        })
+        .collect()
+}
+
+fn fork_sha(sha: &str, deps: &DepSet) -> String {
+    let mut hasher = Hasher::new();
+    hasher.update(sha.as_bytes());
+    for (name, dep_sha) in deps {
+        hasher.update(b"\n");
+        hasher.update(name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(dep_sha.as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+pub fn write_fork_summary(forks: &Vec<ForkedReals>, out: &mut impl Write) -> Result<()> {
+    if forks.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "forked binaries ({}), identical files which resolve to different dependencies get their own reals and symlink farm:",
+        forks.len()
+    )?;
+    for f in forks {
+        writeln!(
+            out,
+            "  {}: sha {} exported as {}",
+            f.path.display(),
+            &f.sha[..f.sha.len().min(12)],
+            &f.forked[..f.forked.len().min(12)]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::{Linked, plan_forks};
+
+    fn linked(sha: &str, deps: &[&str]) -> Linked {
+        Linked {
+            sha: sha.to_string(),
+            deps: deps
+                .iter()
+                .map(|d| {
+                    let path = PathBuf::from(d);
+                    (path.file_name().unwrap().to_string_lossy().to_string(), path)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_plan_forks() {
+        // libfoo is vendored in both wheels, but each wheel has its own libbar
+        // the extensions are identical too, they are forked because their libfoo is
+        let linked = BTreeMap::from([
+            (PathBuf::from("/sp/a.libs/libbar.so"), linked("bar-a", &[])),
+            (PathBuf::from("/sp/b.libs/libbar.so"), linked("bar-b", &[])),
+            (PathBuf::from("/sp/a.libs/libfoo.so"), linked("foo", &["/sp/a.libs/libbar.so"])),
+            (PathBuf::from("/sp/b.libs/libfoo.so"), linked("foo", &["/sp/b.libs/libbar.so"])),
+            (PathBuf::from("/sp/a/_ext.so"), linked("ext", &["/sp/a.libs/libfoo.so"])),
+            (PathBuf::from("/sp/b/_ext.so"), linked("ext", &["/sp/b.libs/libfoo.so"])),
+            // identical copies with identical dependencies still share their reals
+            (PathBuf::from("/sp/c/libz.so"), linked("z", &[])),
+            (PathBuf::from("/sp/d/libz.so"), linked("z", &[])),
+        ]);
+        let forks = plan_forks(&linked);
+        let paths: Vec<&PathBuf> = forks.iter().map(|f| &f.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                &PathBuf::from("/sp/a/_ext.so"),
+                &PathBuf::from("/sp/a.libs/libfoo.so"),
+                &PathBuf::from("/sp/b/_ext.so"),
+                &PathBuf::from("/sp/b.libs/libfoo.so"),
+            ]
+        );
+        assert_ne!(forks[0].forked, forks[2].forked);
+        assert_ne!(forks[1].forked, forks[3].forked);
+        assert_eq!(forks[1].sha, "foo");
+    }
+}
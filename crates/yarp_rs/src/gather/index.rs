@@ -0,0 +1,128 @@
+// every shared library we know of, built once before the walked files are added to the graph
+// a DT_NEEDED entry (or load command) which the platform's search rules can't find is matched against this by name
+// every file is indexed before anything is resolved, so matching is a single pass
+
+use std::{cmp::Ordering, collections::BTreeMap, path::PathBuf};
+
+use crate::{
+    factory::Factory,
+    graph::FileGraph,
+    parse::{KnownLibs, Scopes},
+    pkg::paths::is_maybe_shared_library,
+};
+
+/// where a candidate came from, earlier sources are preferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    // already in the graph: the python executable, manifest loads and libs, and their dependencies
+    Graph,
+    // a file next to a manifest load or lib
+    LoadDir,
+    // a file walked in site-packages, stdlib or lib-dynload
+    Walked,
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    path: PathBuf,
+    source: Source,
+}
+
+#[derive(Debug, Default)]
+pub struct LibIndex {
+    // file name or SONAME to candidates, a candidate is never repeated under a name
+    by_name: BTreeMap<String, Vec<Candidate>>,
+}
+
+impl LibIndex {
+    /// index `path` by its file name, if it looks like a shared library
+    pub fn add_file(&mut self, path: &PathBuf, source: Source) {
+        if !is_maybe_shared_library(path) {
+            return;
+        }
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            self.add(name.to_string(), path, source);
+        }
+    }
+
+    pub fn add(&mut self, name: String, path: &PathBuf, source: Source) {
+        let candidates = self.by_name.entry(name).or_default();
+        match candidates.iter_mut().find(|c| c.path == *path) {
+            Some(existing) => existing.source = existing.source.min(source),
+            None => candidates.push(Candidate {
+                path: path.clone(),
+                source,
+            }),
+        }
+    }
+
+    /// every binary in the graph, by file name and SONAME
+    pub fn add_graph<T: Factory>(&mut self, g: &FileGraph<T>) {
+        for n in g.iter_nodes() {
+            if !n.deps.is_shared_library() {
+                continue;
+            }
+            if let Some(name) = n.path.file_name().and_then(|n| n.to_str()) {
+                self.add(name.to_string(), &n.path, Source::Graph);
+            }
+            if let Some(soname) = n.deps.soname() {
+                self.add(soname, &n.path, Source::Graph);
+            }
+        }
+    }
+
+    /// lookups scoped to the binary asking, `scopes` decide first and the order of sources only breaks ties
+    pub fn known_libs(&self, scopes: Scopes) -> KnownLibs {
+        let by_name = self
+            .by_name
+            .iter()
+            .map(|(name, candidates)| {
+                let mut candidates = candidates.clone();
+                candidates.sort_by(preference);
+                (name.clone(), candidates.into_iter().map(|c| c.path).collect())
+            })
+            .collect();
+        KnownLibs::new(by_name, scopes)
+    }
+}
+
+// the earliest source first, then the shallowest path, then the smallest path
+fn preference(a: &Candidate, b: &Candidate) -> Ordering {
+    a.source
+        .cmp(&b.source)
+        .then_with(|| a.path.components().count().cmp(&b.path.components().count()))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::parse::Scopes;
+
+    use super::{LibIndex, Source};
+
+    #[test]
+    fn test_preference() {
+        let tmp = tempfile::tempdir().unwrap();
+        let deep = tmp.path().join("site-packages/a/b/libgomp.so.1");
+        let shallow = tmp.path().join("site-packages/c/libgomp.so.1");
+        let loaded = tmp.path().join("lib/libgomp.so.1");
+
+        let mut index = LibIndex::default();
+        index.add_file(&deep, Source::Walked);
+        index.add_file(&shallow, Source::Walked);
+        index.add_file(&tmp.path().join("site-packages/a/__init__.py"), Source::Walked);
+
+        let requester = Path::new("/elsewhere/x.so");
+        let known = index.known_libs(Scopes::default());
+        assert_eq!(known.find("libgomp.so.1", requester), Some(shallow.clone()));
+        assert_eq!(known.find("__init__.py", requester), None);
+
+        // something already loaded always wins
+        index.add_file(&loaded, Source::Graph);
+        index.add_file(&shallow, Source::Graph);
+        let known = index.known_libs(Scopes::default());
+        assert_eq!(known.find("libgomp.so.1", requester), Some(loaded));
+    }
+}
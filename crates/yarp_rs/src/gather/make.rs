@@ -1,61 +1,52 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use anyhow::Result;
 use log::info;
-use rayon::current_thread_index;
+use rayon::prelude::*;
 
 use crate::{
     gather::{Factory, NodeFactory},
     node::Node,
+    parse::KnownLibs,
 };
 
+/// make nodes for all `paths` in parallel, parsing, searching and hashing does not need the graph
+/// results are in the same order as `paths`, so adding them to the graph later is deterministic
 pub fn mk_nodes_parallel(
-    node_specs: &Vec<NodeSpec>,
+    paths: &Vec<PathBuf>,
     factory: &NodeFactory,
-) -> (Vec<Node>, Vec<NodeSpec>) {
-    use rayon::prelude::*;
-
-    let empty_known_libs = HashMap::new();
-    let mut res = Vec::new();
-    let mut failures = Vec::new();
-
-    let num_threads = rayon::current_num_threads();
-    let chunk_size = (node_specs.len() + num_threads - 1) / num_threads;
+    known_libs: &KnownLibs,
+    extra_search_paths: &Vec<PathBuf>,
+) -> Vec<Result<Option<Node>>> {
+    let total = paths.len();
+    let done = AtomicUsize::new(0);
     info!(
-        "gather: creating nodes, chunk_size={} threads={}",
-        chunk_size, num_threads
+        "gather: creating nodes, total={} threads={}",
+        total,
+        rayon::current_num_threads()
     );
 
-    let results: Vec<(Vec<Node>, Vec<NodeSpec>)> = node_specs
-        .par_chunks(chunk_size)
-        .map(|chunk| {
-            let thread_idx = current_thread_index().unwrap_or(0);
-            let mut local_res = Vec::new();
-            let mut local_failures = Vec::new();
-            let mut i = 0;
-            let total = chunk.len();
-            for payload in chunk {
-                let node = factory.make_from_spec(payload, &empty_known_libs);
-                match node {
-                    Ok(node) => {
-                        local_res.push(node);
-                    }
-                    Err(_) => {
-                        local_failures.push(payload.clone());
-                    }
-                }
-                i += 1;
-                if total / 10 != 0 && i % (total / 10) == 0 {
-                    info!("thread: {} exported {}/{} files", thread_idx, i, total);
-                }
+    paths
+        .par_iter()
+        .map(|p| {
+            let node = factory.make(p, known_libs, extra_search_paths);
+            let i = done.fetch_add(1, Ordering::Relaxed) + 1;
+            if total / 10 != 0 && i % (total / 10) == 0 {
+                info!("gather: created {}/{} nodes", i, total);
             }
-            (local_res, local_failures)
+            node
         })
-        .collect();
-
-    for (local_res, local_failures) in results {
-        res.extend(local_res);
-        failures.extend(local_failures);
-    }
+        .collect()
+}
 
-    (res, failures)
+/// successfully made nodes by path, `FileGraph::add_tree_with_premade` uses these instead of making the dependencies again
+pub fn premade_by_path(made: &Vec<Result<Option<Node>>>) -> HashMap<PathBuf, Node> {
+    made.iter()
+        .filter_map(|res| res.as_ref().ok().and_then(|n| n.as_ref()))
+        .map(|n| (n.path.clone(), n.clone()))
+        .collect()
 }
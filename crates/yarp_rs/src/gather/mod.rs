@@ -1,26 +1,84 @@
 // use core::unimplemented;
 // // given a yarp manifest, gather all the nodes that we can discover
-use std::{collections::HashMap, path::PathBuf};
+use std::{fs, path::PathBuf};
 
 use anyhow::{Context, Error, Result, anyhow, bail};
-use log::{error, info};
+use log::{debug, info, warn};
 use walkdir::WalkDir;
 
 pub use crate::factory::NodeFactory;
 pub use crate::site_pkgs::PythonPathComponent;
 
+mod filter;
+mod fork;
+mod index;
+mod make;
+mod optional;
+mod prune;
+mod soname;
+
+pub use filter::ExcludedFiles;
+use filter::RootFilters;
+pub use fork::ForkedReals;
+use fork::{fork_conflicting_reals, write_fork_summary};
+use index::{LibIndex, Source};
+use make::{mk_nodes_parallel, premade_by_path};
+pub use optional::UnresolvedOptional;
+use optional::{OptionalLibs, write_unresolved_summary};
+pub use prune::Prune;
+use prune::dist_owners;
+pub use soname::SonameRename;
+use soname::{rename_colliding_sonames, write_rename_summary};
+
 use crate::{
+    error::{Failures, YarpError},
     factory::Factory,
     graph::FileGraph,
     manifest::{LoadKind, YarpManifest},
-    node::{Node, deps::Deps},
+    node::Node,
+    parse::{Ambiguity, KnownLibs, Scopes, write_ambiguity_summary},
     site_pkgs::SitePkgs,
+    target::Target,
 };
 
+/// everything worth telling the user about the gather, other than errors
+#[derive(Debug, Default)]
+pub struct GatherReport {
+    pub excluded: ExcludedFiles,
+    pub pruned: Option<Prune>,
+    pub unresolved_optional: Vec<UnresolvedOptional>,
+    pub ambiguous: Vec<Ambiguity>,
+    pub forked: Vec<ForkedReals>,
+    pub renamed: Vec<SonameRename>,
+}
+
+impl GatherReport {
+    pub fn write_summary(&self, out: &mut impl std::io::Write) -> Result<()> {
+        self.excluded.write_summary(out)?;
+        if let Some(pruned) = &self.pruned {
+            pruned.write_summary(out)?;
+        }
+        write_unresolved_summary(&self.unresolved_optional, out)?;
+        write_ambiguity_summary(&self.ambiguous, out)?;
+        write_fork_summary(&self.forked, out)?;
+        write_rename_summary(&self.renamed, out)
+    }
+}
+
+// decides which of the walked files go in the graph
+struct Walk {
+    filters: RootFilters,
+    prune: Option<Prune>,
+}
+
+/// `prune` keeps only the site-packages distributions in `imports` of the manifest (and their requirements)
+/// binaries are parsed and resolved for `target`, which need not be the host
 pub fn build_graph_from_manifest(
     manifest: &Box<YarpManifest>,
     cwd: &PathBuf,
-) -> Result<(FileGraph<NodeFactory>, Vec<PythonPathComponent>)> {
+    prune: bool,
+    target: Target,
+) -> Result<(FileGraph<NodeFactory>, Vec<PythonPathComponent>, GatherReport)> {
     let site_pkgs = SitePkgs::from_manifest(manifest);
     let factory = NodeFactory::new(
         site_pkgs.clone(),
@@ -29,19 +87,41 @@ pub fn build_graph_from_manifest(
         cwd.clone(),
         manifest.env.clone(),
         manifest.skip.clone(),
+        target,
     );
-    let g = build_graph(manifest, &factory, &site_pkgs)?;
+    let prune = if prune {
+        let imports = manifest.imports.as_ref().ok_or_else(|| {
+            anyhow!("cannot prune site-packages, the manifest does not have `imports`, regenerate it with a newer `yarp.discover`")
+        })?;
+        Some(Prune::new(&get_dist_dirs(&site_pkgs), imports)?)
+    } else {
+        None
+    };
+    let walk = Walk {
+        filters: RootFilters::new(&manifest.filters, &site_pkgs)?,
+        prune,
+    };
+    let optional = OptionalLibs::new(&manifest.optional)?;
+    let mut report = GatherReport::default();
+    let mut g = build_graph(manifest, &factory, &site_pkgs, &walk, &optional, &mut report)?;
+    // forks change shas, renames are by sha
+    report.forked = fork_conflicting_reals(&mut g);
+    report.renamed = rename_colliding_sonames(&mut g);
+    report.pruned = walk.prune;
 
-    Ok((g, site_pkgs.comps))
+    Ok((g, site_pkgs.comps, report))
 }
 
 fn build_graph(
     manifest: &YarpManifest,
     factory: &NodeFactory,
     site_pkgs: &SitePkgs,
+    walk: &Walk,
+    optional: &OptionalLibs,
+    report: &mut GatherReport,
 ) -> Result<FileGraph<NodeFactory>> {
     let executable_path = &manifest.python.sys.executable;
-    let known_libs = HashMap::new();
+    let known_libs = KnownLibs::default();
     let mut g = FileGraph::new(factory.clone());
     info!("Build graph: pass 1, begin");
 
@@ -108,45 +188,17 @@ fn build_graph(
         };
     }
 
-    let mut failures = Vec::new();
-    // add exec prefix, can fail
-    info!("adding stdlib, path={}", site_pkgs.lib_dynload.display());
-    add_nodes_recursive(
-        &mut g,
-        &mut failures,
-        &site_pkgs.lib_dynload,
-        &factory,
-        &known_libs,
-        true,
-        &executable_extra_paths_to_search,
-    )?;
+    // libs are transitive dependencies recorded during discovery, they only go to reals (with their symlink farm)
+    // always serial, should not fail
+    add_libs(&mut g, manifest, factory, &known_libs, &executable_extra_paths_to_search)?;
 
-    // add prefix, can fail
-    info!("adding stdlib, path={}", site_pkgs.stdlib.display());
-    add_nodes_recursive(
-        &mut g,
-        &mut failures,
-        &site_pkgs.stdlib,
-        &factory,
-        &known_libs,
-        true,
-        &executable_extra_paths_to_search,
-    )?;
-
-    // now all site-packages, can fail
-    for (pkg, _) in &site_pkgs.site_pkg_by_alias {
-        info!("adding site-package: path={}", pkg.display());
+    // walk everything upfront, the walked files are also the candidates for libraries the search rules can't find
+    let mut roots = vec![site_pkgs.lib_dynload.clone(), site_pkgs.stdlib.clone()];
+    let mut pkgs: Vec<&PathBuf> = site_pkgs.site_pkg_by_alias.keys().collect();
+    pkgs.sort();
+    for pkg in pkgs {
         if pkg.exists() {
-            // site-packages addition would replace
-            add_nodes_recursive(
-                &mut g,
-                &mut failures,
-                pkg,
-                &factory,
-                &known_libs,
-                true,
-                &executable_extra_paths_to_search,
-            )?;
+            roots.push(pkg.clone());
         } else {
             info!(
                 "site packages at path={} does not exist, skipping",
@@ -154,122 +206,216 @@ fn build_graph(
             );
         }
     }
+    let mut walked = Vec::new();
+    for root in &roots {
+        walked.push(get_paths_recursive_from_dir(root, walk, &mut report.excluded)?);
+    }
+
+    let mut index = LibIndex::default();
+    index.add_graph(&g);
+    for path in manifest.loads.iter().map(|l| &l.path).chain(manifest.libs.iter().map(|l| &l.path)) {
+        add_load_dir(&mut index, path);
+    }
+    for path in walked.iter().flatten() {
+        index.add_file(path, Source::Walked);
+    }
+    let dist_dirs = get_dist_dirs(site_pkgs);
+    let scopes = Scopes {
+        owners: dist_owners(&dist_dirs)?,
+        site_pkgs: dist_dirs,
+        prefixes: vec![
+            manifest.python.sys.prefix.clone(),
+            manifest.python.sys.exec_prefix.clone(),
+        ],
+    };
+    let known_libs = index.known_libs(scopes.clone());
+
+    // stdlib, lib-dynload and all site-packages can fail, walked files replace the nodes added as dependencies
+    let mut failures = Vec::new();
+    for (root, paths) in roots.iter().zip(walked) {
+        info!("adding walked files, root={}", root.display());
+        add_nodes(
+            &mut g,
+            &mut failures,
+            paths,
+            &factory,
+            &known_libs,
+            true,
+            &executable_extra_paths_to_search,
+        );
+    }
 
     add_failures(
         &mut g,
         failures,
         &factory,
+        &mut index,
+        scopes,
         &executable_extra_paths_to_search,
+        optional,
+        report,
     )?;
+    // the retry reports again the names it needed, the walk's ambiguities are kept for the rest
+    for a in known_libs.ambiguities() {
+        if !report.ambiguous.iter().any(|b| b.name == a.name) {
+            report.ambiguous.push(a);
+        }
+    }
+    report.ambiguous.sort_by(|a, b| a.name.cmp(&b.name));
 
     Ok(g)
 }
 
+// files next to a manifest load or lib, libraries are usually installed together
+fn add_load_dir(index: &mut LibIndex, path: &PathBuf) {
+    let entries = match path.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return,
+    };
+    for entry in entries.flatten() {
+        let p = entry.path();
+        if p.is_file() {
+            index.add_file(&p, Source::LoadDir);
+        }
+    }
+}
+
+fn add_libs(
+    g: &mut FileGraph<NodeFactory>,
+    manifest: &YarpManifest,
+    factory: &NodeFactory,
+    known_libs: &KnownLibs,
+    extra_search_paths: &Vec<PathBuf>,
+) -> Result<()> {
+    for (i, lib) in manifest.libs.iter().enumerate() {
+        info!("adding lib detected in manifest, path={}", lib.path.display());
+        factory
+            .make(&lib.path, known_libs, extra_search_paths)
+            .and_then(|n| add_to_graph_if_some(g, n, known_libs, true, extra_search_paths))
+            .with_context(|| {
+                anyhow!(
+                    "failed in resolving lib from manifest, libs[{}] path={}. Make sure the library exists and all its dependencies can be found",
+                    i,
+                    lib.path.display()
+                )
+            })?;
+    }
+    Ok(())
+}
+
+// a single pass over the failures, now also matching SONAMEs of every binary added by the walk
+// the index already has every file we know of, retrying more would not find anything new
 fn add_failures(
     g: &mut FileGraph<NodeFactory>,
     failures: Vec<PathBuf>,
     factory: &NodeFactory,
+    index: &mut LibIndex,
+    scopes: Scopes,
     extra_search_paths: &Vec<PathBuf>,
+    optional: &OptionalLibs,
+    report: &mut GatherReport,
 ) -> Result<()> {
-    // in each cycle, go through all the failures
-    // add them to the graph
-    // if any of them fail, keep them in the new failures vector
-    // if failures do not decrease in a loop, then break and error out with all failures
-    // else go to the next one with the new set of failures
-    // if the failures are 0, break out
-    // keep updating the known_libs values too
-    // updating known_libs is a costly operation (it converts all paths stored in the graph to known_libs in every iteration)
-    // TODO: fix known libs speed, mostly we should be able to fix it if the graph returns all the added nodes in add_tree instead of just the index
-    let mut prev_failures: Vec<(PathBuf, Error)> = failures
-        .iter()
-        .map(|p| (p.clone(), anyhow!("unknown error")))
-        .collect();
-    let mut known_libs = get_libs_from_graph(g);
-
-    let mut i = 0;
-
-    let mut prev_len = prev_failures.len();
-    while prev_len > 0 {
-        i = i + 1;
-        info!("adding failed nodes: Pass {}, length={}", i, prev_len);
-
-        let mut new_failures = Vec::new();
+    if failures.is_empty() {
+        return Ok(());
+    }
+    info!("adding failed nodes, length={}", failures.len());
+    index.add_graph(g);
+    let known_libs = index.known_libs(scopes);
 
-        // failures addition does not recursively replace stuff in the graph
-        for (p, _) in prev_failures {
-            let res = factory
-                .make(&p, &known_libs, extra_search_paths)
-                .and_then(|n| add_to_graph_if_some(g, n, &known_libs, false, extra_search_paths));
-            if let Err(e) = res {
-                new_failures.push((p, e));
-            }
+    let mut new_failures = Vec::new();
+    // failures addition does not recursively replace stuff in the graph
+    for p in failures {
+        if g.get_node_by_path(&p).is_some() {
+            // added as a dependency of an earlier failure
+            continue;
         }
-
-        if new_failures.len() >= prev_len {
-            let errors: Vec<String> = new_failures
-                .into_iter()
-                .map(|(_, e)| format!("{:#}", e))
-                .collect();
-            error!("known libs:");
-            for (lib, path) in known_libs.iter() {
-                error!("{}: {}", lib, path.display());
-            }
-            bail!(
-                "fatal failure in gather, could not find the dependencies of libraries. Errors:\n{}",
-                errors.join("\n\n")
-            );
+        let res = factory
+            .make(&p, &known_libs, extra_search_paths)
+            .and_then(|n| add_to_graph_if_some(g, n, &known_libs, false, extra_search_paths));
+        if let Err(e) = res {
+            new_failures.push((p, e));
         }
+    }
 
-        prev_failures = new_failures;
-        prev_len = prev_failures.len();
+    // optional libraries which are still unresolved are given up on (and exported as is)
+    report.ambiguous = known_libs.ambiguities();
+    let new_failures =
+        add_unresolved_optional(g, new_failures, factory, optional, &mut report.unresolved_optional)?;
+    if new_failures.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(Failures {
+        context: "fatal failure in gather, could not find the dependencies of libraries".to_string(),
+        errors: new_failures.into_iter().map(|(_, e)| e).collect(),
+    }))
+}
 
-        known_libs = get_libs_from_graph(g);
+fn add_unresolved_optional(
+    g: &mut FileGraph<NodeFactory>,
+    failures: Vec<(PathBuf, Error)>,
+    factory: &NodeFactory,
+    optional: &OptionalLibs,
+    unresolved_optional: &mut Vec<UnresolvedOptional>,
+) -> Result<Vec<(PathBuf, Error)>> {
+    let mut remaining = Vec::new();
+    for (p, e) in failures {
+        let is_unresolved = matches!(
+            YarpError::find(&e),
+            Some(YarpError::UnresolvedDependency { .. })
+        );
+        if !is_unresolved || !optional.is_optional(&p) {
+            remaining.push((p, e));
+            continue;
+        }
+        warn!(
+            "optional library could not be resolved, exporting it as is without patching, path={} reason={:#}",
+            p.display(),
+            e
+        );
+        let node = factory.make_unresolved(&p)?;
+        g.add_tree(node, &KnownLibs::default(), false, &Vec::new())?;
+        unresolved_optional.push(UnresolvedOptional {
+            path: p,
+            reason: format!("{:#}", e),
+        });
     }
-    Ok(())
+    Ok(remaining)
 }
 
-fn add_nodes_recursive(
+// paths which fail are pushed to `failures`, they are retried once the whole walk is in the graph
+fn add_nodes(
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
-    directory: &PathBuf,
+    paths: Vec<PathBuf>,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
-) -> Result<()> {
-    if !directory.exists() {
-        bail!(
-            "fatal: tried finding nodes recursively for directory={}, but it does not exist",
-            directory.display()
-        );
-    }
-    let paths = get_paths_recursive_from_dir(directory)?;
+) {
+    let paths: Vec<PathBuf> = paths
+        .into_iter()
+        // skip already done
+        .filter(|p| replace || g.get_node_by_path(p).is_none())
+        .collect();
     let mut i = 0;
     let total = paths.len();
 
-    // TODO: filter out all plain files (!is_maybe_shared_library()) and do them in parallel
-    // we can parallelize the shared library gather to some extent (it would end up doing duplicate node parsing and creation though)
-    // since graph is not safe to use across threads, its difficult to keep track of what all is made
-    // we can create a function which takes a list of known nodes and generates a fresh graph for given nodes in parallel
-    // then we can do batches and keep updating the list of known nodes
-
-    // path_by_node = graph.path_by_node
-    // batch = nodes[:10]
-    // batches = nodes(n batches of size k each)
-    // parallel: for batch in batches:
-        // small_graph = generate_graph(batch, path_by_node)
-    // graph.extend(small_graph)
-
-    for p in paths {
-        if !replace {
-            if let Some(_) = g.get_node_by_path(&p) {
-                // skip already done
-                continue;
-            }
+    // making nodes (parsing, searching, hashing) is the slow part and does not need the graph, it runs in parallel
+    // the graph is then modified serially, in the (sorted) order of paths, so the result does not depend on scheduling
+    let made = mk_nodes_parallel(&paths, factory, known_libs, extra_search_paths);
+    let premade = premade_by_path(&made);
+
+    for (p, res) in paths.into_iter().zip(made) {
+        if !replace && g.get_node_by_path(&p).is_some() {
+            // added as a dependency of an earlier path
+            continue;
         }
-        let res = factory
-            .make(&p, known_libs, extra_search_paths)
-            .and_then(|n| add_to_graph_if_some(g, n, known_libs, replace, extra_search_paths));
+        let res = res.and_then(|n| match n {
+            Some(n) => g
+                .add_tree_with_premade(n, known_libs, replace, extra_search_paths, &premade)
+                .map(|_| ()),
+            None => Ok(()),
+        });
         if let Err(_) = res {
             // this is a hack
             // TODO: if the load is already successful before and we are retrying to reload it, we give up and use the older one
@@ -285,13 +431,12 @@ fn add_nodes_recursive(
             info!("graph: pass 1: {}/{} nodes", i, total);
         }
     }
-    Ok(())
 }
 
 fn add_to_graph_if_some(
     g: &mut FileGraph<NodeFactory>,
     maybe_node: Option<Node>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
 ) -> Result<()> {
@@ -304,33 +449,47 @@ fn add_to_graph_if_some(
     }
 }
 
-fn get_libs_from_graph(g: &FileGraph<NodeFactory>) -> HashMap<String, PathBuf> {
-    let mut known_libs = HashMap::new();
-    for n in g.iter_nodes() {
-        match n.deps {
-            Deps::Plain => {}
-            Deps::Binary(_) => {
-                n.path
-                    .file_name()
-                    .and_then(|file_name| file_name.to_str())
-                    .map(|f| f.to_string())
-                    .map(|f| known_libs.insert(f, n.path.clone()));
-            }
-            #[cfg(test)]
-            Deps::Mock { paths: _ } => {}
-        };
-    }
-    known_libs
+// all sys.path entries which can have distributions, nested site-packages included
+fn get_dist_dirs(site_pkgs: &SitePkgs) -> Vec<PathBuf> {
+    site_pkgs
+        .resolved
+        .iter()
+        .filter(|p| **p != site_pkgs.stdlib && **p != site_pkgs.lib_dynload)
+        .cloned()
+        .collect()
 }
 
-fn get_paths_recursive_from_dir(base_path: &PathBuf) -> Result<Vec<PathBuf>> {
+fn get_paths_recursive_from_dir(
+    base_path: &PathBuf,
+    walk: &Walk,
+    excluded: &mut ExcludedFiles,
+) -> Result<Vec<PathBuf>> {
+    if !base_path.exists() {
+        bail!(
+            "fatal: tried finding nodes recursively for directory={}, but it does not exist",
+            base_path.display()
+        );
+    }
     let mut paths = Vec::new();
-    for maybe_d in WalkDir::new(base_path).into_iter() {
+    // sorted, so that the graph is built in the same order on every run
+    for maybe_d in WalkDir::new(base_path).sort_by_file_name().into_iter() {
         match maybe_d {
             Ok(d) => {
                 let p = d.into_path();
-                if p.is_file() {
-                    paths.push(p);
+                if !p.is_file() {
+                    continue;
+                }
+                // pruned files are reported upfront by `Prune`
+                if walk.prune.as_ref().map(|prune| prune.is_dropped(&p)).unwrap_or(false) {
+                    debug!("pruning path={}", p.display());
+                    continue;
+                }
+                match walk.filters.excluded_by(&p) {
+                    Some((root, glob)) => {
+                        debug!("excluding path={} glob={}", p.display(), glob);
+                        excluded.add(root, glob, &p);
+                    }
+                    None => paths.push(p),
                 }
             }
             Err(e) => {
@@ -0,0 +1,97 @@
+// "plug and play" libraries listed in `optional` in the manifest
+// numba's tbbpool needs TBB, onnxruntime's TensorRT provider needs TensorRT, both are fine to be missing at runtime
+// if such a library can't be resolved even using the library index, we export it as is instead of failing the gather
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+#[derive(Debug)]
+pub struct OptionalLibs {
+    // matched against the file name
+    by_name: GlobSet,
+
+    // matched against the whole path
+    by_path: GlobSet,
+}
+
+impl OptionalLibs {
+    pub fn new(globs: &Vec<String>) -> Result<Self> {
+        let mut by_name = GlobSetBuilder::new();
+        let mut by_path = GlobSetBuilder::new();
+        for g in globs {
+            let glob = Glob::new(g).with_context(|| anyhow!("invalid optional glob: {}", g))?;
+            if g.contains('/') {
+                by_path.add(glob);
+            } else {
+                by_name.add(glob);
+            }
+        }
+        Ok(Self {
+            by_name: by_name.build()?,
+            by_path: by_path.build()?,
+        })
+    }
+
+    pub fn is_optional(&self, path: &Path) -> bool {
+        if self.by_path.is_match(path) {
+            return true;
+        }
+        path.file_name()
+            .map(|name| self.by_name.is_match(name))
+            .unwrap_or(false)
+    }
+}
+
+/// an optional library exported without resolving (or patching) it
+#[derive(Debug)]
+pub struct UnresolvedOptional {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+pub fn write_unresolved_summary(unresolved: &Vec<UnresolvedOptional>, out: &mut impl Write) -> Result<()> {
+    if unresolved.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "unresolved optional libraries ({}), exported as is without patching:",
+        unresolved.len()
+    )?;
+    for u in unresolved {
+        writeln!(out, "  {}: {}", u.path.display(), u.reason)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::OptionalLibs;
+
+    #[test]
+    fn test_is_optional() {
+        let optional = OptionalLibs::new(&vec![
+            "tbbpool*.so".to_string(),
+            "**/onnxruntime/capi/libonnxruntime_providers_tensorrt.so".to_string(),
+        ])
+        .unwrap();
+        assert!(optional.is_optional(&PathBuf::from(
+            "/env/site-packages/numba/np/ufunc/tbbpool.cpython-39-x86_64-linux-gnu.so"
+        )));
+        assert!(optional.is_optional(&PathBuf::from(
+            "/env/site-packages/onnxruntime/capi/libonnxruntime_providers_tensorrt.so"
+        )));
+        assert!(!optional.is_optional(&PathBuf::from(
+            "/env/site-packages/onnxruntime/capi/libonnxruntime_providers_cuda.so"
+        )));
+        // a path glob does not match just the file name
+        assert!(!optional.is_optional(&PathBuf::from("libonnxruntime_providers_tensorrt.so")));
+    }
+}
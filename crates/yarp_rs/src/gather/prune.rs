@@ -0,0 +1,397 @@
+// pruning site-packages to the distributions which were imported at discovery (`imports` in the manifest)
+// every `*.dist-info` in a site-packages owns the files in its RECORD, and provides some top-level modules
+// we keep every distribution providing an imported module, plus everything it (transitively) requires
+// only files owned by the rest are dropped, files not owned by any distribution (a `.pth`, a vendored directory) are kept
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+
+#[derive(Debug)]
+struct Dist {
+    name: String,
+    version: String,
+
+    // all files in RECORD (inside the site-packages), including the dist-info itself
+    files: Vec<PathBuf>,
+    top_level: HashSet<String>,
+    requires: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct DroppedDist {
+    pub name: String,
+    pub version: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Prune {
+    dropped_files: HashSet<PathBuf>,
+    pub kept: Vec<String>,
+    pub dropped: Vec<DroppedDist>,
+}
+
+impl Prune {
+    /// `site_pkgs` are all directories in `sys.path` which can contain distributions
+    pub fn new(site_pkgs: &Vec<PathBuf>, imports: &Vec<String>) -> Result<Self> {
+        let mut dists = Vec::new();
+        for site_pkg in site_pkgs {
+            if site_pkg.is_dir() {
+                dists.extend(find_dists(site_pkg)?);
+            }
+        }
+        let imported: HashSet<&str> = imports
+            .iter()
+            .map(|m| m.split('.').next().unwrap_or(m.as_str()))
+            .collect();
+
+        let mut idx_by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, d) in dists.iter().enumerate() {
+            idx_by_name.entry(normalize_name(&d.name)).or_default().push(i);
+        }
+
+        // the distributions providing an imported module, and the closure of their requirements
+        let mut keep = vec![false; dists.len()];
+        let mut queue: VecDeque<usize> = dists
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.top_level.iter().any(|m| imported.contains(m.as_str())))
+            .map(|(i, _)| i)
+            .collect();
+        while let Some(i) = queue.pop_front() {
+            if keep[i] {
+                continue;
+            }
+            keep[i] = true;
+            for req in &dists[i].requires {
+                // requirements which are not installed are fine, they could be optional or for another platform
+                if let Some(idxs) = idx_by_name.get(req) {
+                    queue.extend(idxs.iter().copied().filter(|j| !keep[*j]));
+                }
+            }
+        }
+
+        let kept_files: HashSet<&PathBuf> = dists
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| **k)
+            .flat_map(|(d, _)| d.files.iter())
+            .collect();
+        let mut prune = Prune::default();
+        for (d, k) in dists.iter().zip(&keep) {
+            if *k {
+                prune.kept.push(d.name.clone());
+                continue;
+            }
+            let mut dropped = DroppedDist {
+                name: d.name.clone(),
+                version: d.version.clone(),
+                files: 0,
+                bytes: 0,
+            };
+            // namespace packages share files between distributions, a file is only dropped if no kept one owns it
+            for f in d.files.iter().filter(|f| !kept_files.contains(f)) {
+                if let Ok(m) = f.metadata() {
+                    dropped.files += 1;
+                    dropped.bytes += m.len();
+                    prune.dropped_files.insert(f.clone());
+                }
+            }
+            prune.dropped.push(dropped);
+        }
+        prune.kept.sort();
+        prune.dropped.sort_by(|a, b| a.name.cmp(&b.name));
+        info!(
+            "prune: keeping {} distributions, dropping {}",
+            prune.kept.len(),
+            prune.dropped.len()
+        );
+        Ok(prune)
+    }
+
+    pub fn is_dropped(&self, path: &Path) -> bool {
+        if self.dropped_files.contains(path) {
+            return true;
+        }
+        // bytecode is written after install and is not in RECORD, `__pycache__/six.cpython-312.pyc` goes with `six.py`
+        let in_pycache = path.parent().and_then(|p| p.file_name()) == Some("__pycache__".as_ref());
+        match (in_pycache, path.parent().and_then(|p| p.parent()), path.file_name().and_then(|n| n.to_str())) {
+            (true, Some(dir), Some(name)) => {
+                let module = name.split('.').next().unwrap_or(name);
+                self.dropped_files.contains(&dir.join(format!("{}.py", module)))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn write_summary(&self, out: &mut impl Write) -> Result<()> {
+        if self.dropped.is_empty() {
+            return Ok(());
+        }
+        let bytes: u64 = self.dropped.iter().map(|d| d.bytes).sum();
+        writeln!(
+            out,
+            "pruned {} distributions which were not imported ({:.1} MB), kept {}:",
+            self.dropped.len(),
+            bytes as f64 / (1024.0 * 1024.0),
+            self.kept.len()
+        )?;
+        for d in &self.dropped {
+            writeln!(out, "  {} {}: {} files", d.name, d.version, d.files)?;
+        }
+        Ok(())
+    }
+}
+
+/// every file in a RECORD to the name of the distribution owning it
+pub fn dist_owners(site_pkgs: &Vec<PathBuf>) -> Result<HashMap<PathBuf, String>> {
+    let mut owners = HashMap::new();
+    for site_pkg in site_pkgs {
+        if !site_pkg.is_dir() {
+            continue;
+        }
+        for d in find_dists(site_pkg)? {
+            let name = normalize_name(&d.name);
+            for f in d.files {
+                owners.insert(f, name.clone());
+            }
+        }
+    }
+    Ok(owners)
+}
+
+fn find_dists(site_pkg: &PathBuf) -> Result<Vec<Dist>> {
+    let mut dists = Vec::new();
+    let entries = fs::read_dir(site_pkg)
+        .with_context(|| anyhow!("failed in listing site-packages, path={}", site_pkg.display()))?;
+    for entry in entries {
+        let p = entry?.path();
+        let is_dist_info = p
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.ends_with(".dist-info"))
+            .unwrap_or(false);
+        if is_dist_info && p.is_dir() {
+            match read_dist(site_pkg, &p) {
+                Ok(d) => dists.push(d),
+                // a broken dist-info is kept as is, we never drop files we don't understand
+                Err(e) => warn!("prune: ignoring distribution at path={}: {:#}", p.display(), e),
+            }
+        }
+    }
+    Ok(dists)
+}
+
+fn read_dist(site_pkg: &PathBuf, dist_info: &PathBuf) -> Result<Dist> {
+    let metadata = fs::read_to_string(dist_info.join("METADATA"))
+        .with_context(|| anyhow!("failed in reading METADATA"))?;
+    let headers = parse_metadata(&metadata);
+    let name = headers
+        .get("Name")
+        .and_then(|v| v.first())
+        .cloned()
+        .ok_or_else(|| anyhow!("METADATA does not have a Name"))?;
+    let version = headers
+        .get("Version")
+        .and_then(|v| v.first())
+        .cloned()
+        .unwrap_or_default();
+    let requires = headers
+        .get("Requires-Dist")
+        .map(|reqs| reqs.iter().filter_map(|r| parse_requirement(r)).collect())
+        .unwrap_or_default();
+
+    let record = fs::read_to_string(dist_info.join("RECORD"))
+        .with_context(|| anyhow!("failed in reading RECORD"))?;
+    let record: Vec<String> = record.lines().filter_map(parse_record_line).collect();
+    let mut files: Vec<PathBuf> = record
+        .iter()
+        // scripts are installed outside site-packages, like `../../../bin/f2py`
+        .filter(|r| !r.starts_with("..") && !r.starts_with('/'))
+        .map(|r| site_pkg.join(r))
+        .collect();
+    files.push(dist_info.join("RECORD"));
+
+    let top_level = match fs::read_to_string(dist_info.join("top_level.txt")) {
+        Ok(t) => t
+            .lines()
+            .map(|l| l.trim().replace('/', "."))
+            .filter(|l| !l.is_empty())
+            .map(|l| l.split('.').next().unwrap_or_default().to_string())
+            .collect(),
+        Err(_) => record.iter().filter_map(|r| module_of_record(r)).collect(),
+    };
+    Ok(Dist {
+        name,
+        version,
+        files,
+        top_level,
+        requires,
+    })
+}
+
+// RFC 822 style headers, only till the first empty line (the description follows it)
+fn parse_metadata(metadata: &str) -> HashMap<String, Vec<String>> {
+    let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+    for line in metadata.lines() {
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            headers
+                .entry(k.trim().to_string())
+                .or_default()
+                .push(v.trim().to_string());
+        }
+    }
+    headers
+}
+
+// `requests (>=2.0) ; python_version >= "3"` -> requests
+// requirements only needed for an extra are ignored, we can't know if the extra was installed
+fn parse_requirement(req: &str) -> Option<String> {
+    let (req, marker) = req.split_once(';').unwrap_or((req, ""));
+    if marker.contains("extra") {
+        return None;
+    }
+    let name: String = req
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.')
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(normalize_name(&name))
+    }
+}
+
+// first column of a RECORD line, it is CSV so a path with a comma is quoted
+fn parse_record_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    if let Some(rest) = line.strip_prefix('"') {
+        let mut path = String::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    path.push('"');
+                } else {
+                    return Some(path);
+                }
+            } else {
+                path.push(c);
+            }
+        }
+        None
+    } else {
+        line.split(',').next().map(|p| p.to_string())
+    }
+}
+
+// top-level module for a RECORD entry: `numpy/core/x.py` -> numpy, `six.py` -> six, `_cffi_backend.cpython-39-x86_64-linux-gnu.so` -> _cffi_backend
+fn module_of_record(record: &str) -> Option<String> {
+    let first = record.split('/').next()?;
+    if first.starts_with("..")
+        || first == "__pycache__"
+        || first.ends_with(".dist-info")
+        || first.ends_with(".pth")
+    {
+        return None;
+    }
+    let module = first.split('.').next()?;
+    if module.is_empty() {
+        None
+    } else {
+        Some(module.to_string())
+    }
+}
+
+// PEP 503
+fn normalize_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_sep = false;
+    for c in name.chars() {
+        if c == '-' || c == '_' || c == '.' {
+            if !prev_sep {
+                out.push('-');
+            }
+            prev_sep = true;
+        } else {
+            out.push(c.to_ascii_lowercase());
+            prev_sep = false;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use tempfile::tempdir;
+
+    use super::{Prune, parse_record_line, parse_requirement};
+
+    fn mk_dist(site_pkg: &Path, name: &str, requires: &[&str], files: &[&str]) {
+        let dist_info = site_pkg.join(format!("{}-1.0.dist-info", name));
+        fs::create_dir_all(&dist_info).unwrap();
+        let mut metadata = format!("Metadata-Version: 2.1\nName: {}\nVersion: 1.0\n", name);
+        for r in requires {
+            metadata.push_str(&format!("Requires-Dist: {}\n", r));
+        }
+        metadata.push_str("\nsome description: with a colon\n");
+        fs::write(dist_info.join("METADATA"), metadata).unwrap();
+        let mut record = String::new();
+        for f in files {
+            let p = site_pkg.join(f);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(&p, "x").unwrap();
+            record.push_str(&format!("{},sha256=abc,1\n", f));
+        }
+        record.push_str(&format!("{}-1.0.dist-info/METADATA,,\n", name));
+        fs::write(dist_info.join("RECORD"), record).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_requirement_closure() {
+        let dir = tempdir().unwrap();
+        let site_pkg = dir.path().join("site-packages");
+        mk_dist(&site_pkg, "Flask", &["Werkzeug (>=2.0)", "pytest ; extra == \"test\""], &["flask/__init__.py"]);
+        mk_dist(&site_pkg, "werkzeug", &[], &["werkzeug/__init__.py"]);
+        mk_dist(&site_pkg, "pytest", &[], &["pytest/__init__.py", "_pytest/x.py"]);
+        mk_dist(&site_pkg, "six", &[], &["six.py"]);
+        fs::write(site_pkg.join("distutils-precedence.pth"), "").unwrap();
+
+        let prune = Prune::new(&vec![site_pkg.clone()], &vec!["flask.app".to_string()]).unwrap();
+        assert_eq!(prune.kept, vec!["Flask", "werkzeug"]);
+        let dropped: Vec<&str> = prune.dropped.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(dropped, vec!["pytest", "six"]);
+        assert!(prune.is_dropped(&site_pkg.join("_pytest/x.py")));
+        assert!(prune.is_dropped(&site_pkg.join("six-1.0.dist-info/METADATA")));
+        assert!(!prune.is_dropped(&site_pkg.join("werkzeug/__init__.py")));
+        assert!(!prune.is_dropped(&site_pkg.join("distutils-precedence.pth")));
+        assert!(prune.is_dropped(&site_pkg.join("__pycache__/six.cpython-312.pyc")));
+        assert!(!prune.is_dropped(&site_pkg.join("werkzeug/__pycache__/__init__.cpython-312.pyc")));
+    }
+
+    #[test]
+    fn test_parse_helpers() {
+        assert_eq!(parse_requirement("Typing_Extensions>=4; python_version < \"3.11\""), Some("typing-extensions".to_string()));
+        assert_eq!(parse_requirement("pytest; extra == 'test'"), None);
+        assert_eq!(parse_record_line("\"a,b.py\",sha256=x,1"), Some("a,b.py".to_string()));
+        assert_eq!(parse_record_line("numpy/__init__.py,sha256=x,1"), Some("numpy/__init__.py".to_string()));
+    }
+}
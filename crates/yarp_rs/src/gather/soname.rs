@@ -0,0 +1,249 @@
+// ld.so keeps a single library per name in a process (see docs/NESTING.md)
+// two different files asked for with the same name (torch and scikit-learn both vendor a libgomp.so.1) can't both be loaded,
+// whichever is loaded first is used for the dependents of the other too
+// like auditwheel, colliding libraries are exported with their sha in the SONAME, and their dependents' DT_NEEDED is rewritten
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::{
+    factory::Factory,
+    graph::FileGraph,
+    node::{Pkg, deps::Deps},
+    parse::Binary,
+};
+
+/// a library exported with a new SONAME
+#[derive(Debug, Clone)]
+pub struct SonameRename {
+    // the file name it was asked for with
+    pub old: String,
+
+    pub new: String,
+
+    // every copy of the library, all with the same sha
+    pub paths: Vec<PathBuf>,
+
+    // binaries whose DT_NEEDED is rewritten to `new`
+    pub dependents: Vec<PathBuf>,
+}
+
+// a library which is asked for by name
+#[derive(Debug)]
+struct NamedLib {
+    path: PathBuf,
+
+    sha: String,
+
+    // file name and SONAME, after export dependents ask for the file name, and ld.so also matches SONAMEs of loaded libraries
+    names: BTreeSet<String>,
+
+    dependents: Vec<PathBuf>,
+
+    // needed by the python executable, which is never patched, so its name can't change
+    pinned: bool,
+}
+
+/// detect ELF libraries with the same name but different content, and rename them in the graph
+/// everything in the graph is loaded by the same python process, so any two libraries can collide
+pub fn rename_colliding_sonames<T: Factory>(g: &mut FileGraph<T>) -> Vec<SonameRename> {
+    let renames = plan_renames(&named_libs(g));
+    for rename in &renames {
+        info!(
+            "renaming {} to {} to avoid a SONAME collision, paths={:?}",
+            rename.old, rename.new, rename.paths
+        );
+        for path in &rename.paths {
+            if let Some(node) = g.get_node_by_path_mut(path) {
+                node.deps.set_new_soname(&rename.new);
+            }
+        }
+        for dependent in &rename.dependents {
+            if let Some(node) = g.get_node_by_path_mut(dependent) {
+                for path in &rename.paths {
+                    node.deps.rename_needed(path, &rename.new);
+                }
+            }
+        }
+    }
+    renames
+}
+
+fn named_libs<T: Factory>(g: &FileGraph<T>) -> Vec<NamedLib> {
+    let mut libs = Vec::new();
+    for node in g.iter_nodes() {
+        let binary = match &node.deps {
+            Deps::Binary(binary @ Binary::Elf(_)) => binary,
+            _ => continue,
+        };
+        let sha = match node.pkg.sha() {
+            Some(sha) => sha,
+            None => continue,
+        };
+        let dependents = g.get_node_dependents(node);
+        // only loaded by path (like python extensions), no lookup by name can find these
+        if dependents.is_empty() {
+            continue;
+        }
+        let mut names = BTreeSet::new();
+        names.extend(node.path.file_name().map(|n| n.to_string_lossy().to_string()));
+        names.extend(binary.soname());
+        let mut dependent_paths: Vec<PathBuf> = dependents.iter().map(|d| d.path.clone()).collect();
+        dependent_paths.sort();
+        libs.push(NamedLib {
+            path: node.path.clone(),
+            sha: sha.to_string(),
+            names,
+            dependents: dependent_paths,
+            pinned: dependents.iter().any(|d| matches!(d.pkg, Pkg::Executable)),
+        });
+    }
+    libs.sort_by(|a, b| a.path.cmp(&b.path));
+    libs
+}
+
+fn plan_renames(libs: &Vec<NamedLib>) -> Vec<SonameRename> {
+    let mut shas_by_name: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for lib in libs {
+        for name in &lib.names {
+            shas_by_name.entry(name).or_default().insert(&lib.sha);
+        }
+    }
+    let pinned: BTreeSet<&str> = libs.iter().filter(|l| l.pinned).map(|l| l.sha.as_str()).collect();
+
+    let mut to_rename: BTreeSet<&str> = BTreeSet::new();
+    for (name, shas) in &shas_by_name {
+        if shas.len() < 2 {
+            continue;
+        }
+        let kept: Vec<&str> = shas.iter().filter(|s| pinned.contains(*s)).cloned().collect();
+        if kept.len() > 1 {
+            warn!(
+                "libraries with shas {:?} are all needed as {} by the python executable, which is not patched, only one of them will be loaded",
+                kept, name
+            );
+        }
+        to_rename.extend(shas.iter().filter(|s| !pinned.contains(*s)));
+    }
+
+    // copies of a library share their reals, they are renamed together
+    let mut renames = Vec::new();
+    for sha in to_rename {
+        let copies: Vec<&NamedLib> = libs.iter().filter(|l| l.sha == sha).collect();
+        let old = copies[0]
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut dependents: Vec<PathBuf> = copies.iter().flat_map(|l| l.dependents.clone()).collect();
+        dependents.sort();
+        dependents.dedup();
+        renames.push(SonameRename {
+            new: hashed_name(&old, sha),
+            old,
+            paths: copies.iter().map(|l| l.path.clone()).collect(),
+            dependents,
+        });
+    }
+    renames.sort_by(|a, b| a.old.cmp(&b.old).then_with(|| a.new.cmp(&b.new)));
+    renames
+}
+
+// libgomp.so.1 with sha 0123abcd... is exported as libgomp-0123abcd.so.1
+fn hashed_name(name: &str, sha: &str) -> String {
+    let short = &sha[..sha.len().min(8)];
+    let so = name
+        .match_indices(".so")
+        .map(|(i, _)| i)
+        .find(|i| {
+            let rest = &name[i + 3..];
+            rest.is_empty() || rest.starts_with('.')
+        });
+    match so {
+        Some(i) => format!("{}-{}{}", &name[..i], short, &name[i..]),
+        None => format!("{}-{}", name, short),
+    }
+}
+
+pub fn write_rename_summary(renames: &Vec<SonameRename>, out: &mut impl Write) -> Result<()> {
+    if renames.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "renamed libraries ({}), different libraries are needed with the same name and ld.so loads only one of them:",
+        renames.len()
+    )?;
+    for r in renames {
+        writeln!(
+            out,
+            "  {} -> {} (needed by {} binaries)",
+            r.old,
+            r.new,
+            r.dependents.len()
+        )?;
+        for path in &r.paths {
+            writeln!(out, "    {}", path.display())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, path::PathBuf};
+
+    use super::{NamedLib, hashed_name, plan_renames};
+
+    fn lib(path: &str, sha: &str, dependents: &[&str], pinned: bool) -> NamedLib {
+        let path = PathBuf::from(path);
+        NamedLib {
+            names: BTreeSet::from([path.file_name().unwrap().to_string_lossy().to_string()]),
+            path,
+            sha: sha.to_string(),
+            dependents: dependents.iter().map(PathBuf::from).collect(),
+            pinned,
+        }
+    }
+
+    #[test]
+    fn test_plan_renames() {
+        let libs = vec![
+            lib("/sp/torch/lib/libgomp.so.1", "aaaaaaaaaaaa", &["/sp/torch/lib/libtorch.so"], false),
+            lib("/sp/scikit_learn.libs/libgomp.so.1", "bbbbbbbbbbbb", &["/sp/sklearn/_openmp.so"], false),
+            // copies of the same file don't collide
+            lib("/sp/a/libfoo.so", "cccccccccccc", &["/sp/a/x.so"], false),
+            lib("/sp/b/libfoo.so", "cccccccccccc", &["/sp/b/x.so"], false),
+            // the executable's libz keeps its name
+            lib("/env/lib/libz.so.1", "dddddddddddd", &["/env/bin/python"], true),
+            lib("/sp/pillow.libs/libz.so.1", "eeeeeeeeeeee", &["/sp/PIL/_imaging.so"], false),
+        ];
+        let renames = plan_renames(&libs);
+        let summary: Vec<(&str, &str, usize)> = renames
+            .iter()
+            .map(|r| (r.old.as_str(), r.new.as_str(), r.paths.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("libgomp.so.1", "libgomp-aaaaaaaa.so.1", 1),
+                ("libgomp.so.1", "libgomp-bbbbbbbb.so.1", 1),
+                ("libz.so.1", "libz-eeeeeeee.so.1", 1),
+            ]
+        );
+        assert_eq!(renames[2].dependents, vec![PathBuf::from("/sp/PIL/_imaging.so")]);
+    }
+
+    #[test]
+    fn test_hashed_name() {
+        assert_eq!(hashed_name("libgomp.so.1.0.0", "0123456789"), "libgomp-01234567.so.1.0.0");
+        assert_eq!(hashed_name("libfoo.sound.so.2", "0123456789"), "libfoo.sound-01234567.so.2");
+        assert_eq!(hashed_name("libcrypto", "0123456789"), "libcrypto-01234567");
+    }
+}
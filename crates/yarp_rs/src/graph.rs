@@ -1,15 +1,36 @@
-use std::{collections::HashMap, fmt::Display, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    path::PathBuf,
+};
 
 use anyhow::{Context, Result, anyhow};
 use bimap::BiHashMap;
 use log::info;
-use petgraph::{Direction::Incoming, Graph, algo::toposort, graph::NodeIndex, visit::EdgeRef};
+use petgraph::{
+    Direction::{self, Incoming, Outgoing},
+    Graph,
+    algo::{tarjan_scc, toposort},
+    graph::NodeIndex,
+    visit::EdgeRef,
+};
 
-use crate::{factory::Factory, node::Node};
+use crate::{error::YarpError, factory::Factory, node::Node, parse::KnownLibs};
+
+/// an edge from a dependency to its dependent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    // the dependency is exported before its dependent
+    Ordered,
+
+    // LC_LOAD_UPWARD_DYLIB, the dependency usually depends on us too
+    // it is linked in our symlink farm, but does not decide the export order
+    Upward,
+}
 
 #[derive(Debug)]
 pub struct FileGraph<T: Factory> {
-    inner: Graph<(), ()>,
+    inner: Graph<(), Edge>,
     idx_by_path: BiHashMap<NodeIndex, PathBuf>,
     path_by_node: HashMap<PathBuf, Node>,
     factory: T,
@@ -89,50 +110,126 @@ impl<T: Factory> FileGraph<T> {
     pub fn add_tree(
         &mut self,
         node: Node,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
+        replace: bool,
+        search_paths: &Vec<PathBuf>,
+    ) -> Result<NodeIndex> {
+        self.add_tree_with_premade(node, known_libs, replace, search_paths, &HashMap::new())
+    }
+
+    /// the same as `add_tree`, dependencies found in `premade` are used as is instead of making them using the factory
+    /// nodes are premade in parallel, the graph itself is always modified serially
+    pub fn add_tree_with_premade(
+        &mut self,
+        node: Node,
+        known_libs: &KnownLibs,
+        replace: bool,
+        search_paths: &Vec<PathBuf>,
+        premade: &HashMap<PathBuf, Node>,
+    ) -> Result<NodeIndex> {
+        let mut in_progress = HashSet::new();
+        let mut back_edges = Vec::new();
+        let res = self.add_tree_inner(
+            node,
+            known_libs,
+            replace,
+            search_paths,
+            premade,
+            &mut in_progress,
+            &mut back_edges,
+        );
+        // a dependency which was still being added when it was needed again is a cycle, link it now
+        for (dep, dependent, edge) in back_edges {
+            if let (Some(dep_idx), Some(dependent_idx)) = (
+                self.idx_by_path.get_by_right(&dep),
+                self.idx_by_path.get_by_right(&dependent),
+            ) {
+                if !self.inner.contains_edge(*dep_idx, *dependent_idx) {
+                    self.inner.add_edge(*dep_idx, *dependent_idx, edge);
+                }
+            }
+        }
+        res
+    }
+
+    // `in_progress` are the nodes on the current path of the recursion, they are added to the graph only after their dependencies
+    // needing one of them again is a cycle, the edge is recorded in `back_edges` instead of recursing forever
+    fn add_tree_inner(
+        &mut self,
+        node: Node,
+        known_libs: &KnownLibs,
         replace: bool,
         search_paths: &Vec<PathBuf>,
+        premade: &HashMap<PathBuf, Node>,
+        in_progress: &mut HashSet<PathBuf>,
+        back_edges: &mut Vec<(PathBuf, PathBuf, Edge)>,
     ) -> Result<NodeIndex> {
         let deps = node.deps.find()?;
+        let upward: HashSet<PathBuf> = node.deps.upward().into_iter().collect();
 
-        let extra_search_paths = node.deps.paths_to_add_for_next_search();
-        let mut search_paths = search_paths.clone();
-        search_paths.extend(extra_search_paths);
+        // a stack, the rpaths of the nearest loader come first, like ld.so and dyld search them
+        let mut search_paths_for_deps = node.deps.paths_to_add_for_next_search();
+        search_paths_for_deps.extend(search_paths.iter().cloned());
+        let search_paths = search_paths_for_deps;
 
+        in_progress.insert(node.path.clone());
         let mut all_parent_idx = Vec::new();
         for p in deps {
+            let edge = if upward.contains(&p) { Edge::Upward } else { Edge::Ordered };
             if let Some(parent_idx) = self.idx_by_path.get_by_right(&p) {
-                all_parent_idx.push(*parent_idx);
+                all_parent_idx.push((*parent_idx, edge));
+                continue;
+            }
+            if in_progress.contains(&p) {
+                back_edges.push((p, node.path.clone(), edge));
                 continue;
             }
-            let parent_node = self.factory.make(&p, known_libs, &search_paths)?;
+            let parent_node = match premade.get(&p) {
+                Some(n) => Some(n.clone()),
+                None => self.factory.make(&p, known_libs, &search_paths)?,
+            };
             if let Some(parent_node) = parent_node {
                 info!("adding node recursively in graph, path={}", p.display());
                 let parent_idx = self
-                    .add_tree(parent_node, known_libs, false, &search_paths)
+                    .add_tree_inner(
+                        parent_node,
+                        known_libs,
+                        false,
+                        &search_paths,
+                        premade,
+                        in_progress,
+                        back_edges,
+                    )
                     .context(anyhow!("file: {}", p.display()))?;
-                all_parent_idx.push(parent_idx);
+                all_parent_idx.push((parent_idx, edge));
             }
         }
+        in_progress.remove(&node.path);
 
         let idx = self.add_node(node, replace);
-        for parent_idx in all_parent_idx {
-            self.inner.add_edge(parent_idx, idx, ());
+        for (parent_idx, edge) in all_parent_idx {
             if !self.inner.contains_edge(parent_idx, idx) {
-                self.inner.add_edge(parent_idx, idx, ());
+                self.inner.add_edge(parent_idx, idx, edge);
             }
         }
         Ok(idx)
     }
 
-
     pub fn get_node_by_path(&self, path: &PathBuf) -> Option<&Node> {
         self.path_by_node.get(path)
     }
 
+    // only for rewriting what a node is exported as, edges are never touched
+    pub fn get_node_by_path_mut(&mut self, path: &PathBuf) -> Option<&mut Node> {
+        self.path_by_node.get_mut(path)
+    }
+
     pub fn toposort(&self) -> Result<impl Iterator<Item = Node>> {
-        let node_indices = toposort(&self.inner, None)
-            .map_err(|e| anyhow!("{:#?}", e))
+        let node_indices = toposort(&self.ordered(), None)
+            .map_err(|_| match self.find_cycle() {
+                Some(paths) => anyhow::Error::new(YarpError::DependencyCycle { paths }),
+                None => anyhow!("graph has a cycle"),
+            })
             .context("dependency analysis failed")?;
 
         Ok(node_indices.into_iter().map(|idx| {
@@ -148,6 +245,277 @@ impl<T: Factory> FileGraph<T> {
         }))
     }
 
+    /// nodes grouped by their depth in the graph, a node only depends on nodes in earlier levels
+    /// all nodes in a level can be exported concurrently once the earlier levels are done
+    /// each level is sorted by path, so the order is deterministic
+    /// upward Mach-O dependencies don't count, they are usually dependents of the node too
+    /// fails with `YarpError::DependencyCycle` if files depend on each other in a cycle, use `component_levels` to handle those
+    pub fn levels(&self) -> Result<Vec<Vec<Node>>> {
+        let mut levels = Vec::new();
+        for components in self.component_levels() {
+            let mut level = Vec::new();
+            for component in components {
+                if self.is_cycle(&component) {
+                    let members: HashSet<NodeIndex> = component
+                        .iter()
+                        .map(|n| *self.idx_by_path.get_by_right(&n.path).expect("corrupted graph state"))
+                        .collect();
+                    return Err(self.cycle_error(&members));
+                }
+                level.extend(component);
+            }
+            levels.push(level);
+        }
+        Ok(levels)
+    }
+
+    /// like `levels`, but every entry of a level is a strongly connected component instead of a single node
+    /// a component with more than one node, or a single node depending on itself, is a cycle (see `is_cycle`)
+    /// a component only depends on components in earlier levels, components and their nodes are sorted by path
+    pub fn component_levels(&self) -> Vec<Vec<Vec<Node>>> {
+        let components = tarjan_scc(&self.ordered());
+        let mut component_of: HashMap<NodeIndex, usize> = HashMap::new();
+        for (i, component) in components.iter().enumerate() {
+            for idx in component {
+                component_of.insert(*idx, i);
+            }
+        }
+
+        // kahn's algorithm over components, edges inside a component are ignored
+        let mut pending_deps = vec![0; components.len()];
+        for edge in self.inner.edge_references().filter(|e| *e.weight() == Edge::Ordered) {
+            let (from, to) = (component_of[&edge.source()], component_of[&edge.target()]);
+            if from != to {
+                pending_deps[to] += 1;
+            }
+        }
+        let mut current: Vec<usize> = (0..components.len()).filter(|i| pending_deps[*i] == 0).collect();
+
+        let mut levels = Vec::new();
+        while !current.is_empty() {
+            let mut next = Vec::new();
+            for c in &current {
+                for idx in &components[*c] {
+                    for dependent in self.ordered_neighbors(*idx, Outgoing) {
+                        let d = component_of[&dependent];
+                        if d == *c {
+                            continue;
+                        }
+                        pending_deps[d] -= 1;
+                        if pending_deps[d] == 0 {
+                            next.push(d);
+                        }
+                    }
+                }
+            }
+            let mut level: Vec<Vec<Node>> = current
+                .iter()
+                .map(|c| {
+                    let mut nodes: Vec<Node> = components[*c]
+                        .iter()
+                        .map(|idx| self.get_node_by_index_or_panic(*idx))
+                        .collect();
+                    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+                    nodes
+                })
+                .collect();
+            level.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+            levels.push(level);
+            current = next;
+        }
+        levels
+    }
+
+    /// whether a component of `component_levels` is a dependency cycle, a file linking itself is one too
+    pub fn is_cycle(&self, component: &[Node]) -> bool {
+        match component {
+            [node] => {
+                let idx = *self.idx_by_path.get_by_right(&node.path).expect("corrupted graph state");
+                self.ordered_neighbors(idx, Outgoing).any(|d| d == idx)
+            }
+            _ => component.len() > 1,
+        }
+    }
+
+    // the graph without upward edges, which is what the export order is decided on
+    // node indices are the same as in `inner`
+    fn ordered(&self) -> Graph<(), Edge> {
+        self.inner
+            .filter_map(|_, _| Some(()), |_, e| (*e == Edge::Ordered).then_some(*e))
+    }
+
+    fn ordered_neighbors(&self, idx: NodeIndex, dir: Direction) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.inner
+            .edges_directed(idx, dir)
+            .filter(|e| *e.weight() == Edge::Ordered)
+            .map(move |e| if dir == Outgoing { e.target() } else { e.source() })
+    }
+
+    /// the files of some dependency cycle in the graph, each depends on the next and the last on the first
+    pub fn find_cycle(&self) -> Option<Vec<PathBuf>> {
+        let component = tarjan_scc(&self.ordered())
+            .into_iter()
+            .filter(|c| c.len() > 1 || self.ordered_neighbors(c[0], Outgoing).any(|d| d == c[0]))
+            .min_by_key(|c| c.iter().map(|idx| self.idx_by_path.get_by_left(idx)).min())?;
+        Some(self.cycle_in(&component.into_iter().collect()))
+    }
+
+    fn cycle_error(&self, members: &HashSet<NodeIndex>) -> anyhow::Error {
+        anyhow::Error::new(YarpError::DependencyCycle {
+            paths: self.cycle_in(members),
+        })
+    }
+
+    // a shortest cycle through the smallest path of a strongly connected component
+    // walks from a node to its dependencies, so every path in the result depends on the next one
+    fn cycle_in(&self, members: &HashSet<NodeIndex>) -> Vec<PathBuf> {
+        let start = *members
+            .iter()
+            .min_by_key(|idx| self.idx_by_path.get_by_left(idx))
+            .expect("a component is never empty");
+        let mut parent: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        let mut last = None;
+        'search: while let Some(idx) = queue.pop_front() {
+            let mut deps: Vec<NodeIndex> = self
+                .ordered_neighbors(idx, Incoming)
+                .filter(|d| members.contains(d))
+                .collect();
+            deps.sort_by_key(|d| self.idx_by_path.get_by_left(d));
+            for dep in deps {
+                if dep == start {
+                    last = Some(idx);
+                    break 'search;
+                }
+                if !parent.contains_key(&dep) {
+                    parent.insert(dep, idx);
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        let mut cycle = Vec::new();
+        let mut idx = last.expect("a strongly connected component always has a cycle through every node");
+        while idx != start {
+            cycle.push(idx);
+            idx = parent[&idx];
+        }
+        cycle.push(start);
+        cycle
+            .into_iter()
+            .rev()
+            .map(|idx| self.idx_by_path.get_by_left(&idx).expect("corrupted graph state").clone())
+            .collect()
+    }
+
+    /// `path` and all its transitive dependencies, sorted by path
+    pub fn reachable_from(&self, path: &PathBuf) -> Result<Vec<Node>> {
+        let start = self
+            .idx_by_path
+            .get_by_right(path)
+            .ok_or_else(|| anyhow!("path is not in the graph, path={}", path.display()))?;
+        let mut seen = HashSet::from([*start]);
+        let mut stack = vec![*start];
+        while let Some(idx) = stack.pop() {
+            for dep in self.inner.neighbors_directed(idx, Incoming) {
+                if seen.insert(dep) {
+                    stack.push(dep);
+                }
+            }
+        }
+        let mut nodes: Vec<Node> = seen
+            .into_iter()
+            .map(|idx| self.get_node_by_index_or_panic(idx))
+            .collect();
+        nodes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(nodes)
+    }
+
+    /// the shortest chains of dependents from `path` to the nearest roots, every chain starts at `path` and ends at a root
+    /// all chains of the shortest length are returned (at most `limit`), sorted by their paths
+    /// `path` itself is not a root for this, unless none of its dependents lead to one, then it is its own chain
+    pub fn shortest_chains_to_roots(
+        &self,
+        path: &PathBuf,
+        is_root: impl Fn(&Node) -> bool,
+        limit: usize,
+    ) -> Result<Vec<Vec<Node>>> {
+        let start = *self
+            .idx_by_path
+            .get_by_right(path)
+            .ok_or_else(|| anyhow!("path is not in the graph, path={}", path.display()))?;
+
+        // breadth first over dependents, remembering every parent on a shortest path
+        let mut parents: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::from([(start, Vec::new())]);
+        let mut current = vec![start];
+        let mut roots = Vec::new();
+        while !current.is_empty() {
+            // every walked file is a root, a site-packages file would otherwise only explain itself
+            roots = current
+                .iter()
+                .filter(|idx| **idx != start && is_root(&self.get_node_by_index_or_panic(**idx)))
+                .copied()
+                .collect();
+            if !roots.is_empty() {
+                break;
+            }
+            let mut next = Vec::new();
+            for idx in &current {
+                for dependent in self.inner.neighbors_directed(*idx, Outgoing) {
+                    match parents.get_mut(&dependent) {
+                        Some(ps) => {
+                            // reached again from the same level, another shortest path
+                            if next.contains(&dependent) && !ps.contains(idx) {
+                                ps.push(*idx);
+                            }
+                        }
+                        None => {
+                            parents.insert(dependent, vec![*idx]);
+                            next.push(dependent);
+                        }
+                    }
+                }
+            }
+            current = next;
+        }
+        if roots.is_empty() && is_root(&self.get_node_by_index_or_panic(start)) {
+            return Ok(vec![vec![self.get_node_by_index_or_panic(start)]]);
+        }
+
+        let mut chains = Vec::new();
+        for root in roots {
+            let mut partial = vec![vec![root]];
+            while let Some(chain) = partial.pop() {
+                let last = *chain.last().expect("chain is never empty");
+                if last == start {
+                    chains.push(chain);
+                    continue;
+                }
+                for parent in &parents[&last] {
+                    let mut next = chain.clone();
+                    next.push(*parent);
+                    partial.push(next);
+                }
+            }
+        }
+
+        let mut chains: Vec<Vec<Node>> = chains
+            .into_iter()
+            .map(|chain| {
+                chain
+                    .into_iter()
+                    .rev()
+                    .map(|idx| self.get_node_by_index_or_panic(idx))
+                    .collect()
+            })
+            .collect();
+        chains.sort_by(|a: &Vec<Node>, b: &Vec<Node>| {
+            a.iter().map(|n| &n.path).cmp(b.iter().map(|n| &n.path))
+        });
+        chains.truncate(limit);
+        Ok(chains)
+    }
+
     pub fn get_node_dependencies(&self, node: &Node) -> Vec<Node> {
         // given a node, return all the dependencies of the node
         self.idx_by_path
@@ -161,6 +529,19 @@ impl<T: Factory> FileGraph<T> {
             .unwrap_or(vec![])
     }
 
+    pub fn get_node_dependents(&self, node: &Node) -> Vec<Node> {
+        // given a node, return all the nodes which depend on it
+        self.idx_by_path
+            .get_by_right(&node.path)
+            .map(|idx| {
+                self.inner
+                    .edges_directed(*idx, Outgoing)
+                    .map(|e| self.get_node_by_index_or_panic(e.target()))
+                    .collect::<Vec<Node>>()
+            })
+            .unwrap_or(vec![])
+    }
+
     fn get_node_by_index_or_panic(&self, idx: NodeIndex) -> Node {
         let path = self.idx_by_path.get_by_left(&idx).expect(&format!(
             "corrupted graph state: could not find path for idx in edge, idx={:?}",
@@ -185,6 +566,14 @@ mod test {
 
     use std::collections::HashMap;
 
+    use crate::{
+        factory::NodeFactory,
+        manifest::{Skip, Version},
+        node::{Pkg, deps::Deps},
+        parse::{Binary, DylibKind, Macho, SearchStep},
+        site_pkgs::SitePkgs,
+    };
+
     struct MockFactory {
         pub path_by_deps: HashMap<PathBuf, Vec<PathBuf>>,
     }
@@ -199,7 +588,7 @@ mod test {
         fn make(
             &self,
             path: &PathBuf,
-            _known_libs: &HashMap<String, PathBuf>,
+            _known_libs: &KnownLibs,
             _extra_search_paths: &Vec<PathBuf>,
         ) -> Result<Option<Node>> {
             let deps = self
@@ -211,7 +600,7 @@ mod test {
         }
 
         fn make_py_executable(&self, path: &PathBuf) -> Result<Node> {
-            self.make(path, &HashMap::new(), &Vec::new())
+            self.make(path, &KnownLibs::default(), &Vec::new())
                 .map(|n| n.unwrap())
         }
 
@@ -219,10 +608,10 @@ mod test {
             &self,
             path: &PathBuf,
             _symlinks: &Vec<String>,
-            _known_libs: &HashMap<String, PathBuf>,
+            _known_libs: &KnownLibs,
             _extra_search_paths: &Vec<PathBuf>,
         ) -> Result<Option<Node>> {
-            self.make(path, &HashMap::new(), &Vec::new())
+            self.make(path, &KnownLibs::default(), &Vec::new())
         }
     }
     fn create_temp_dir() -> tempfile::TempDir {
@@ -240,7 +629,7 @@ mod test {
         let path = touch_path(&tmp, "python");
         let node = Node::mock(path, vec![]).unwrap();
         let idx = graph
-            .add_tree(node, &HashMap::new(), false, &Vec::new())
+            .add_tree(node, &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1);
         assert!(graph.idx_by_path.contains_left(&idx));
@@ -258,7 +647,7 @@ mod test {
         let mut graph = get_graph(HashMap::from([(p_python, vec![p_lib_test])]));
 
         graph
-            .add_tree(py_node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(py_node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 2);
         assert_eq!(graph.inner.edge_count(), 1);
@@ -275,16 +664,35 @@ mod test {
         let node = Node::mock(p_python, vec![]).unwrap();
 
         graph
-            .add_tree(node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1);
 
         graph
-            .add_tree(node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1); // Should not add duplicate
     }
 
+    #[test]
+    fn test_add_duplicate_node_keeps_one_edge() {
+        let tmp = create_temp_dir();
+        let p_lib_test = touch_path(&tmp, "libtest");
+        let p_python = touch_path(&tmp, "python");
+        let py_node = Node::mock(p_python.clone(), vec![p_lib_test.clone()]).unwrap();
+        let mut graph = get_graph(HashMap::new());
+
+        graph
+            .add_tree(py_node.clone(), &KnownLibs::default(), false, &Vec::new())
+            .unwrap();
+        // replacing a node re-links its dependencies, which are already linked
+        graph
+            .add_tree(py_node.clone(), &KnownLibs::default(), true, &Vec::new())
+            .unwrap();
+        assert_eq!(graph.inner.node_count(), 2);
+        assert_eq!(graph.inner.edge_count(), 1);
+    }
+
     #[test]
     fn test_add_node_complex_dependencies() {
         println!("*************start complex test**********************");
@@ -311,7 +719,7 @@ mod test {
         ]);
         let mut graph = get_graph(path_by_deps);
 
-        let result = graph.add_tree(main.clone(), &HashMap::new(), false, &Vec::new());
+        let result = graph.add_tree(main.clone(), &KnownLibs::default(), false, &Vec::new());
         println!("*************end complex adding**********************");
         assert!(result.is_ok());
         assert_eq!(graph.inner.node_count(), 4);
@@ -330,6 +738,333 @@ mod test {
         assert_before(&nodes, &dep3, &main);
     }
 
+    #[test]
+    fn test_add_tree_with_premade() {
+        let tmp = create_temp_dir();
+        let p_lib_a = touch_path(&tmp, "liba");
+        let p_lib_b = touch_path(&tmp, "libb");
+        let p_python = touch_path(&tmp, "python");
+        // the factory does not know liba depends on libb, only the premade node does
+        let mut graph = get_graph(HashMap::new());
+        let premade = HashMap::from([(
+            p_lib_a.clone(),
+            Node::mock(p_lib_a.clone(), vec![p_lib_b.clone()]).unwrap(),
+        )]);
+        let py_node = Node::mock(p_python.clone(), vec![p_lib_a.clone()]).unwrap();
+
+        graph
+            .add_tree_with_premade(py_node, &KnownLibs::default(), false, &Vec::new(), &premade)
+            .unwrap();
+        assert_eq!(graph.inner.node_count(), 3);
+        assert_eq!(graph.inner.edge_count(), 2);
+        assert!(graph.idx_by_path.contains_right(&p_lib_b));
+    }
+
+    #[test]
+    fn test_levels() {
+        let tmp = create_temp_dir();
+        let dep2_path = touch_path(&tmp, "dep2.py");
+        let dep1_path = touch_path(&tmp, "libdep1");
+        let dep3_path = touch_path(&tmp, "libdep3");
+        let main_path = touch_path(&tmp, "python");
+        let path_by_deps = HashMap::from([
+            (main_path.clone(), vec![dep1_path.clone(), dep2_path.clone()]),
+            (dep1_path.clone(), vec![dep3_path.clone()]),
+            (dep3_path.clone(), vec![dep2_path.clone()]),
+            (dep2_path.clone(), vec![]),
+        ]);
+        let main = Node::mock(main_path.clone(), vec![dep1_path.clone(), dep2_path.clone()]).unwrap();
+        let mut graph = get_graph(path_by_deps);
+        graph
+            .add_tree(main, &KnownLibs::default(), false, &Vec::new())
+            .unwrap();
+
+        let levels: Vec<Vec<PathBuf>> = graph
+            .levels()
+            .unwrap()
+            .into_iter()
+            .map(|l| l.into_iter().map(|n| n.path).collect())
+            .collect();
+        // main depends on dep2 directly, but it still has to wait for dep1
+        assert_eq!(
+            levels,
+            vec![vec![dep2_path], vec![dep3_path], vec![dep1_path], vec![main_path]]
+        );
+    }
+
+    #[test]
+    fn test_reachable_from() {
+        let tmp = create_temp_dir();
+        let dep1_path = touch_path(&tmp, "libdep1");
+        let dep2_path = touch_path(&tmp, "libdep2");
+        let main_path = touch_path(&tmp, "python");
+        let other_path = touch_path(&tmp, "other");
+        let path_by_deps = HashMap::from([
+            (dep1_path.clone(), vec![dep2_path.clone()]),
+            (dep2_path.clone(), vec![]),
+        ]);
+        let mut graph = get_graph(path_by_deps);
+        let main = Node::mock(main_path.clone(), vec![dep1_path.clone()]).unwrap();
+        let other = Node::mock(other_path.clone(), vec![dep2_path.clone()]).unwrap();
+        graph.add_tree(main, &KnownLibs::default(), false, &Vec::new()).unwrap();
+        graph.add_tree(other, &KnownLibs::default(), false, &Vec::new()).unwrap();
+
+        let reachable: Vec<PathBuf> = graph
+            .reachable_from(&dep1_path)
+            .unwrap()
+            .into_iter()
+            .map(|n| n.path)
+            .collect();
+        assert_eq!(reachable, vec![dep1_path, dep2_path]);
+        assert!(graph.reachable_from(&tmp.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_shortest_chains_to_roots() {
+        let tmp = create_temp_dir();
+        let lib_path = touch_path(&tmp, "libgfortran");
+        let blas_path = touch_path(&tmp, "libblas");
+        let lapack_path = touch_path(&tmp, "liblapack");
+        let ext_path = touch_path(&tmp, "ext");
+        let far_path = touch_path(&tmp, "far");
+        let path_by_deps = HashMap::from([
+            (lib_path.clone(), vec![]),
+            (blas_path.clone(), vec![lib_path.clone()]),
+            (lapack_path.clone(), vec![lib_path.clone()]),
+        ]);
+        let mut graph = get_graph(path_by_deps);
+        // ext reaches libgfortran through two libraries, far only through a longer chain
+        let ext = Node::mock(ext_path.clone(), vec![blas_path.clone(), lapack_path.clone()]).unwrap();
+        let far = Node::mock(far_path.clone(), vec![ext_path.clone()]).unwrap();
+        graph.add_tree(ext, &KnownLibs::default(), false, &Vec::new()).unwrap();
+        graph.add_tree(far, &KnownLibs::default(), false, &Vec::new()).unwrap();
+
+        let roots = [ext_path.clone(), far_path.clone()];
+        let chains: Vec<Vec<PathBuf>> = graph
+            .shortest_chains_to_roots(&lib_path, |n| roots.contains(&n.path), 10)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.into_iter().map(|n| n.path).collect())
+            .collect();
+        assert_eq!(
+            chains,
+            vec![
+                vec![lib_path.clone(), blas_path.clone(), ext_path.clone()],
+                vec![lib_path.clone(), lapack_path.clone(), ext_path.clone()],
+            ]
+        );
+
+        // a root with dependents is explained by them
+        let chains: Vec<Vec<PathBuf>> = graph
+            .shortest_chains_to_roots(&ext_path, |n| roots.contains(&n.path), 10)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.into_iter().map(|n| n.path).collect())
+            .collect();
+        assert_eq!(chains, vec![vec![ext_path.clone(), far_path.clone()]]);
+
+        // a root without dependents is its own chain
+        let chains = graph
+            .shortest_chains_to_roots(&far_path, |n| roots.contains(&n.path), 10)
+            .unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].len(), 1);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let tmp = create_temp_dir();
+        let mkl_path = touch_path(&tmp, "libmkl");
+        let omp_path = touch_path(&tmp, "libomp");
+        let ext_path = touch_path(&tmp, "ext");
+        let path_by_deps = HashMap::from([
+            (mkl_path.clone(), vec![omp_path.clone()]),
+            (omp_path.clone(), vec![mkl_path.clone()]),
+        ]);
+        let mut graph = get_graph(path_by_deps);
+        let ext = Node::mock(ext_path.clone(), vec![mkl_path.clone()]).unwrap();
+        graph.add_tree(ext, &KnownLibs::default(), false, &Vec::new()).unwrap();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.inner.edge_count(), 3);
+
+        let err = graph.levels().unwrap_err();
+        match YarpError::find(&err) {
+            Some(YarpError::DependencyCycle { paths }) => {
+                assert_eq!(paths, &vec![mkl_path.clone(), omp_path.clone()])
+            }
+            other => panic!("expected a dependency cycle, found {:?}", other),
+        }
+        assert_eq!(graph.find_cycle(), Some(vec![mkl_path.clone(), omp_path.clone()]));
+
+        let levels: Vec<Vec<Vec<PathBuf>>> = graph
+            .component_levels()
+            .into_iter()
+            .map(|l| l.into_iter().map(|c| c.into_iter().map(|n| n.path).collect()).collect())
+            .collect();
+        assert_eq!(levels, vec![vec![vec![mkl_path, omp_path]], vec![vec![ext_path]]]);
+    }
+
+    #[test]
+    fn test_self_loop_is_cycle() {
+        let tmp = create_temp_dir();
+        let lib_path = touch_path(&tmp, "libself");
+        let ext_path = touch_path(&tmp, "ext");
+        let mut graph = get_graph(HashMap::from([(lib_path.clone(), vec![lib_path.clone()])]));
+        let ext = Node::mock(ext_path.clone(), vec![lib_path.clone()]).unwrap();
+        graph.add_tree(ext, &KnownLibs::default(), false, &Vec::new()).unwrap();
+        assert_eq!(graph.inner.edge_count(), 2);
+
+        let err = graph.levels().unwrap_err();
+        match YarpError::find(&err) {
+            Some(YarpError::DependencyCycle { paths }) => assert_eq!(paths, &vec![lib_path.clone()]),
+            other => panic!("expected a dependency cycle, found {:?}", other),
+        }
+        assert_eq!(graph.find_cycle(), Some(vec![lib_path.clone()]));
+        assert!(graph.toposort().is_err());
+
+        let levels = graph.component_levels();
+        assert_eq!(levels.len(), 2);
+        assert!(graph.is_cycle(&levels[0][0]));
+        assert!(!graph.is_cycle(&levels[1][0]));
+    }
+
+    #[test]
+    fn test_upward_edges_do_not_order() {
+        // libkinds loads libe upward and libe loads libkinds, like two dylibs of a framework
+        let tmp = create_temp_dir();
+        let kinds_path = touch_path(&tmp, "libkinds.dylib");
+        let e_path = touch_path(&tmp, "libe.dylib");
+        let macho = |path: &PathBuf, dep: &PathBuf, kind: DylibKind| {
+            let load_cmd = format!("@rpath/{}", dep.file_name().unwrap().to_string_lossy());
+            let mach = Macho {
+                load_cmds: HashMap::from([(load_cmd.clone(), dep.clone())]),
+                resolutions: HashMap::new(),
+                rpaths: HashMap::new(),
+                id_dylib: None,
+                path: path.clone(),
+                all_rpaths: Vec::new(),
+                kinds: HashMap::from([(load_cmd, kind)]),
+                missing_weak: Vec::new(),
+            };
+            let sha = path.file_name().unwrap().to_string_lossy().to_string();
+            Node::new(path.clone(), Pkg::Binary { sha }, Deps::Binary(Binary::Macho(mach))).unwrap()
+        };
+        let kinds = macho(&kinds_path, &e_path, DylibKind::Upward);
+        let e = macho(&e_path, &kinds_path, DylibKind::Load);
+        let mut graph = get_graph(HashMap::new());
+        graph
+            .add_tree_with_premade(
+                kinds.clone(),
+                &KnownLibs::default(),
+                false,
+                &Vec::new(),
+                &HashMap::from([(e_path.clone(), e.clone())]),
+            )
+            .unwrap();
+        assert_eq!(graph.inner.edge_count(), 2);
+
+        // exported without --allow-cycles, libkinds first
+        let levels: Vec<Vec<PathBuf>> = graph
+            .levels()
+            .unwrap()
+            .into_iter()
+            .map(|l| l.into_iter().map(|n| n.path).collect())
+            .collect();
+        assert_eq!(levels, vec![vec![kinds_path.clone()], vec![e_path.clone()]]);
+        assert_eq!(graph.find_cycle(), None);
+        assert!(graph.toposort().is_ok());
+        // libe is still linked in the symlink farm of libkinds
+        assert_eq!(graph.get_node_dependencies(&kinds), vec![e]);
+    }
+
+    fn macho_factory(cwd: &PathBuf) -> NodeFactory {
+        NodeFactory::new(
+            SitePkgs {
+                resolved: Vec::new(),
+                site_pkg_by_alias: HashMap::new(),
+                lib_dynload: PathBuf::from("/env/lib/python3.12/lib-dynload"),
+                stdlib: PathBuf::from("/env/lib/python3.12"),
+                comps: Vec::new(),
+            },
+            Version {
+                major: 3,
+                minor: 12,
+                abi_thread: String::new(),
+            },
+            PathBuf::from("/env/bin/python"),
+            cwd.clone(),
+            HashMap::new(),
+            Skip {
+                prefixes: Vec::new(),
+                libs: Vec::new(),
+            },
+            "macos-aarch64".parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_export_order_with_upward_dylib() {
+        // generated by fixtures/macho/gen.py, libkinds loads libe upward and libe loads libkinds
+        let lib = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/macho/lib");
+        let factory = macho_factory(&lib);
+        let kinds = factory
+            .make(&lib.join("libkinds.dylib"), &KnownLibs::default(), &Vec::new())
+            .unwrap()
+            .unwrap();
+        let mut graph = FileGraph::new(factory);
+        graph
+            .add_tree(kinds.clone(), &KnownLibs::default(), false, &Vec::new())
+            .unwrap();
+        assert_eq!(graph.inner.node_count(), 5);
+
+        // no --allow-cycles needed, libe is exported after libkinds but still linked in its farm
+        let levels: Vec<Vec<PathBuf>> = graph
+            .levels()
+            .unwrap()
+            .into_iter()
+            .map(|l| l.into_iter().map(|n| n.path).collect())
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                vec![lib.join("libb.dylib"), lib.join("libc.dylib"), lib.join("libd.dylib")],
+                vec![lib.join("libkinds.dylib")],
+                vec![lib.join("libe.dylib")],
+            ]
+        );
+        let deps = graph.get_node_dependencies(&kinds);
+        assert!(deps.iter().any(|n| n.path == lib.join("libe.dylib")));
+    }
+
+    #[test]
+    fn test_add_tree_inherits_macho_rpaths() {
+        // generated by fixtures/macho/gen.py, libchild only finds libleaf through the LC_RPATH of libparent
+        let chain = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/macho/chain");
+        let factory = macho_factory(&chain);
+        let parent = factory
+            .make(&chain.join("libparent.dylib"), &KnownLibs::default(), &Vec::new())
+            .unwrap()
+            .unwrap();
+        let mut graph = FileGraph::new(factory);
+        graph
+            .add_tree(parent, &KnownLibs::default(), false, &Vec::new())
+            .unwrap();
+        assert_eq!(graph.inner.node_count(), 3);
+        assert_eq!(graph.inner.edge_count(), 2);
+
+        let child = graph
+            .get_node_by_path(&chain.join("deps/libchild.dylib"))
+            .unwrap();
+        match &child.deps {
+            Deps::Binary(Binary::Macho(macho)) => {
+                let resolution = &macho.resolutions["@rpath/libleaf.dylib"];
+                assert_eq!(resolution.winner, chain.join("deps/libleaf.dylib"));
+                assert_eq!(resolution.step, SearchStep::InheritedLcRpath);
+            }
+            _ => panic!("expected a Mach-O"),
+        }
+    }
+
     fn assert_before(vec: &Vec<Node>, first: &Node, second: &Node) {
         let first_pos = vec
             .iter()
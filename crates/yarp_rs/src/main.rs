@@ -1,21 +1,25 @@
-use std::{env, path::PathBuf};
+use std::process::ExitCode;
 
+use clap::Parser;
 use log::info;
 
-use crate::{
-    gather::{build_graph_from_manifest, NodeFactory}, graph::FileGraph, manifest::YarpManifest, paths::normalize_path, pkg::{bootstrap::write_bootstrap_script, move_to_dist}
-};
+use crate::cli::Cli;
 
+pub mod cli;
+pub mod cmd;
 pub mod digest;
+pub mod error;
 pub mod gather;
 pub mod graph;
 pub mod manifest;
 pub mod node;
 pub mod paths;
 pub mod pkg;
+pub mod preflight;
 pub mod parse;
 pub mod factory;
 pub mod site_pkgs;
+pub mod target;
 
 /**
  * Algorithm:
@@ -51,65 +55,18 @@ pub mod site_pkgs;
  * reals is same for both, how they are symlinked to correct location is different
  */
 
-fn main() {
+fn main() -> ExitCode {
     env_logger::init();
+    let cli = Cli::parse();
     let start_time = std::time::Instant::now();
-    export_files();
+    let res = cmd::run(cli);
     let duration = start_time.elapsed();
     info!("Time to finish: {} seconds", duration.as_secs());
-}
-
-fn export_files() {
-    let args: Vec<String> = std::env::args().collect();
-    let yarp_manifest_path = args
-        .get(1)
-        .expect("Expected a single argument, the path the yarp manifest");
-    let manifest_contents = std::fs::read_to_string(yarp_manifest_path).expect(&format!(
-        "Failed to read yarp manifest file {}",
-        yarp_manifest_path
-    ));
-    let manifest = get_manifest(&manifest_contents);
-    let cwd = env::current_dir().unwrap();
-
-    let (graph, path_components) =
-        build_graph_from_manifest(&manifest, &cwd).expect("failed in building graph");
-    let dist = cwd.join("dist");
-    if dist.exists() {
-        info!("found existing dist, removing. path={}", dist.display());
-        std::fs::remove_dir_all(&dist).expect(&format!(
-            "Failed to remove existing dist directory at {}",
-            dist.display()
-        ));
-    }
-    move_all_nodes(&graph, &dist);
-    write_bootstrap_script(&dist, &path_components, &manifest.python.sys.version)
-        .expect("failed in writing bootstrap script");
-}
-
-fn get_manifest(manifest_contents: &str) -> Box<YarpManifest> {
-    let mut manifest: YarpManifest =
-        serde_json::from_str(manifest_contents).expect("Failed to parse yarp manifest as JSON");
-    manifest.python.sys.path = manifest
-        .python
-        .sys
-        .path
-        .iter()
-        .map(|p| normalize_path(p))
-        .collect();
-    Box::new(manifest)
-}
-
-fn move_all_nodes(graph: &FileGraph<NodeFactory>, dist: &PathBuf) {
-    info!("exporting files to dist");
-    let total = graph.len();
-    let mut i = 0;
-    // TODO: parallelize this (we need custom toposort implementation)
-    for node in graph.toposort().unwrap() {
-        let deps = graph.get_node_dependencies(&node);
-        move_to_dist(&node, &deps, dist).unwrap();
-        i += 1;
-        if total / 10 != 0 && i % (total / 10) == 0 {
-            info!("exported {}/{} files", i, total);
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprint!("{}", error::render(&e));
+            ExitCode::FAILURE
         }
     }
 }
@@ -0,0 +1,274 @@
+// merge manifests from multiple discovery runs (say, one of the API server and one of the test suite) into one
+// all runs must use the same python, everything else (including filters) is a union (in the order of the manifests)
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{Error, Result, bail};
+use log::warn;
+
+use super::{Load, LoadKind, MANIFEST_VERSION, Sys, YarpManifest};
+use crate::error::{Failures, YarpError};
+
+// colon separated search paths, values from all manifests are joined instead of picking one
+const SEARCH_PATH_VARS: [&str; 5] = [
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "DYLD_LIBRARY_PATH",
+    "DYLD_FALLBACK_LIBRARY_PATH",
+    "PATH",
+];
+
+/// merge `manifests`, each is paired with the path it was read from for error messages
+pub fn merge_manifests(manifests: Vec<(PathBuf, YarpManifest)>) -> Result<YarpManifest> {
+    let mut manifests = manifests.into_iter();
+    let (first_path, mut merged) = match manifests.next() {
+        Some(m) => m,
+        None => bail!("no manifests to merge"),
+    };
+    merged.version = MANIFEST_VERSION;
+
+    for (path, manifest) in manifests {
+        check_same_python(&first_path, &merged.python.sys, &path, &manifest.python.sys)?;
+        merged.target = match (merged.target, manifest.target) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(Error::new(YarpError::ManifestInvalid {
+                    path: path.clone(),
+                    reason: format!(
+                        "target is {}, but it is {} in manifest at path={}",
+                        b,
+                        a,
+                        first_path.display()
+                    ),
+                }));
+            }
+            (a, b) => a.or(b),
+        };
+        for p in manifest.python.sys.path {
+            push_unique(&mut merged.python.sys.path, p);
+        }
+        merge_loads(&mut merged.loads, manifest.loads);
+        for lib in manifest.libs {
+            if !merged.libs.iter().any(|l| l.path == lib.path) {
+                merged.libs.push(lib);
+            }
+        }
+        for p in manifest.skip.prefixes {
+            push_unique(&mut merged.skip.prefixes, p);
+        }
+        for lib in manifest.skip.libs {
+            push_unique(&mut merged.skip.libs, lib);
+        }
+        merge_env(&mut merged.env, manifest.env, &path);
+        merged.filters.extend(manifest.filters);
+        for g in manifest.optional {
+            push_unique(&mut merged.optional, g);
+        }
+        // pruning needs the imports of every run, if any of them does not have it we can't prune
+        merged.imports = match (merged.imports.take(), manifest.imports) {
+            (Some(mut imports), Some(others)) => {
+                for m in others {
+                    push_unique(&mut imports, m);
+                }
+                Some(imports)
+            }
+            _ => None,
+        };
+    }
+    Ok(merged)
+}
+
+fn check_same_python(first_path: &PathBuf, first: &Sys, path: &PathBuf, other: &Sys) -> Result<()> {
+    let mut errors = Vec::new();
+    let mut check = |field: &str, a: String, b: String| {
+        if a != b {
+            errors.push(Error::new(YarpError::ManifestInvalid {
+                path: path.clone(),
+                reason: format!(
+                    "python.sys.{} is {}, but it is {} in manifest at path={}",
+                    field,
+                    b,
+                    a,
+                    first_path.display()
+                ),
+            }));
+        }
+    };
+    check(
+        "executable",
+        first.executable.display().to_string(),
+        other.executable.display().to_string(),
+    );
+    check(
+        "version",
+        first.version.get_python_version(),
+        other.version.get_python_version(),
+    );
+    check(
+        "prefix",
+        first.prefix.display().to_string(),
+        other.prefix.display().to_string(),
+    );
+    check(
+        "exec_prefix",
+        first.exec_prefix.display().to_string(),
+        other.exec_prefix.display().to_string(),
+    );
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(Failures {
+            context: "cannot merge manifests of different pythons".to_string(),
+            errors,
+        }))
+    }
+}
+
+fn merge_loads(loads: &mut Vec<Load>, others: Vec<Load>) {
+    let mut idx_by_path: HashMap<PathBuf, usize> = loads
+        .iter()
+        .enumerate()
+        .map(|(i, l)| (l.path.clone(), i))
+        .collect();
+    for other in others {
+        match idx_by_path.get(&other.path) {
+            Some(&i) => {
+                let load = &mut loads[i];
+                // a dlopen-ed extension needs to be in the library path too, dlopen wins
+                if load.kind != other.kind {
+                    warn!(
+                        "path={} is loaded both as an extension and using dlopen, exporting it as dlopen",
+                        load.path.display()
+                    );
+                    load.kind = LoadKind::Dlopen;
+                }
+                for s in other.symlinks {
+                    push_unique(&mut load.symlinks, s);
+                }
+            }
+            None => {
+                idx_by_path.insert(other.path.clone(), loads.len());
+                loads.push(other);
+            }
+        }
+    }
+}
+
+fn merge_env(env: &mut HashMap<String, String>, other: HashMap<String, String>, path: &PathBuf) {
+    // iterate in a fixed order, warnings should not jump around between runs
+    let mut other: Vec<(String, String)> = other.into_iter().collect();
+    other.sort();
+    for (k, v) in other {
+        match env.get(&k) {
+            None => {
+                env.insert(k, v);
+            }
+            Some(existing) if *existing == v => {}
+            Some(existing) => {
+                if SEARCH_PATH_VARS.contains(&k.as_str()) {
+                    let joined = join_search_paths(existing, &v);
+                    env.insert(k, joined);
+                } else {
+                    warn!(
+                        "env var {} differs in manifest at path={}, keeping the first value",
+                        k,
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn join_search_paths(a: &str, b: &str) -> String {
+    let mut seen = HashSet::new();
+    a.split(':')
+        .chain(b.split(':'))
+        .filter(|p| !p.is_empty() && seen.insert(*p))
+        .collect::<Vec<&str>>()
+        .join(":")
+}
+
+fn push_unique<T: PartialEq>(v: &mut Vec<T>, item: T) {
+    if !v.contains(&item) {
+        v.push(item);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use crate::{
+        error::YarpError,
+        manifest::{LoadKind, YarpManifest, parse_manifest},
+    };
+
+    use super::merge_manifests;
+
+    fn manifest(loads: serde_json::Value, env: serde_json::Value, minor: u32) -> YarpManifest {
+        let m = json!({
+            "version": 1,
+            "loads": loads,
+            "libs": [{"path": "/env/lib/libz.so"}],
+            "python": {"sys": {
+                "prefix": "/env",
+                "exec_prefix": "/env",
+                "platlibdir": "lib",
+                "version": {"major": 3, "minor": minor, "abi_thread": ""},
+                "path": ["/env/lib/python3.12/site-packages"],
+                "executable": "/env/bin/python",
+            }},
+            "env": env,
+            "skip": {"prefixes": [], "libs": []}
+        });
+        parse_manifest(&m.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_merge_unions_loads_and_env() {
+        let a = manifest(
+            json!([{"kind": "extension", "path": "/env/lib/libfoo.so", "symlinks": ["foo"]}]),
+            json!({"LD_LIBRARY_PATH": "/a:/b", "HOME": "/home/a"}),
+            12,
+        );
+        let b = manifest(
+            json!([
+                {"kind": "dlopen", "path": "/env/lib/libfoo.so", "symlinks": ["foo", "foo2"]},
+                {"kind": "dlopen", "path": "/env/lib/libbar.so", "symlinks": []}
+            ]),
+            json!({"LD_LIBRARY_PATH": "/b:/c", "HOME": "/home/b"}),
+            12,
+        );
+        let merged = merge_manifests(vec![
+            (PathBuf::from("a.json"), a),
+            (PathBuf::from("b.json"), b),
+        ])
+        .unwrap();
+        assert_eq!(merged.loads.len(), 2);
+        assert!(matches!(merged.loads[0].kind, LoadKind::Dlopen));
+        assert_eq!(merged.loads[0].symlinks, vec!["foo", "foo2"]);
+        assert_eq!(merged.libs.len(), 1);
+        assert_eq!(merged.env["LD_LIBRARY_PATH"], "/a:/b:/c");
+        assert_eq!(merged.env["HOME"], "/home/a");
+    }
+
+    #[test]
+    fn test_merge_fails_on_different_python() {
+        let a = manifest(json!([]), json!({}), 12);
+        let b = manifest(json!([]), json!({}), 13);
+        let err = merge_manifests(vec![
+            (PathBuf::from("a.json"), a),
+            (PathBuf::from("b.json"), b),
+        ])
+        .unwrap_err();
+        let failures = err.downcast_ref::<crate::error::Failures>().unwrap();
+        assert_eq!(failures.errors.len(), 1);
+        let typed = YarpError::find(&failures.errors[0]).unwrap();
+        assert!(typed.to_string().contains("python.sys.version"));
+    }
+}
@@ -0,0 +1,72 @@
+// upgrades older manifests to the current version
+// migrations work on raw JSON, the structs in `manifest` only ever describe the latest version
+// to make a breaking change: bump `MANIFEST_VERSION` and add a migration from the previous version to `MIGRATIONS`
+
+use log::info;
+use serde_json::{Map, Value};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+// `MIGRATIONS[i]` upgrades a manifest from version `i` to `i + 1`
+const MIGRATIONS: [Migration; MANIFEST_VERSION as usize] = [v0_to_v1];
+
+pub fn migrate(mut value: Value) -> Result<Value, String> {
+    let obj = value
+        .as_object_mut()
+        .ok_or_else(|| "the manifest must be a JSON object".to_string())?;
+    let mut version = get_version(obj)?;
+    if version > MANIFEST_VERSION {
+        return Err(format!(
+            "version: manifest version {} is newer than the latest version {} supported by this yarp, upgrade yarp",
+            version, MANIFEST_VERSION
+        ));
+    }
+    while version < MANIFEST_VERSION {
+        info!("migrating manifest from version {} to {}", version, version + 1);
+        MIGRATIONS[version as usize](obj)?;
+        version += 1;
+        obj.insert("version".to_string(), Value::from(version));
+    }
+    Ok(value)
+}
+
+// manifests written before versioning do not have the field at all, they are version 0
+fn get_version(obj: &Map<String, Value>) -> Result<u32, String> {
+    match obj.get("version") {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| format!("version: expected a non negative integer, found {}", v)),
+    }
+}
+
+// version 0 is the same as version 1 without the `version` field, which is added by `migrate`
+fn v0_to_v1(_: &mut Map<String, Value>) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{MANIFEST_VERSION, migrate};
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let migrated = migrate(json!({"loads": []})).unwrap();
+        assert_eq!(migrated["version"], json!(MANIFEST_VERSION));
+        assert_eq!(migrated["loads"], json!([]));
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_and_invalid() {
+        let err = migrate(json!({"version": MANIFEST_VERSION + 1})).unwrap_err();
+        assert!(err.contains("upgrade yarp"), "{}", err);
+        let err = migrate(json!({"version": "one"})).unwrap_err();
+        assert!(err.starts_with("version:"), "{}", err);
+        assert!(migrate(json!([])).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context, Error, Result, anyhow, bail};
+use log::warn;
+use schemars::{JsonSchema, Schema, schema_for};
+
+/// the module defining types for deserializing yarp.json (or called yarp manifest)
+/// an example json is in this test module, code is duplicated between `python/yarp` and our crate
+/// both should always be synced, `yarp manifest schema` emits the JSON Schema python can validate against
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Failures, YarpError},
+    paths::normalize_path,
+    target::Target,
+};
+
+mod merge;
+mod migrate;
+mod validate;
+
+pub use merge::merge_manifests;
+pub use migrate::MANIFEST_VERSION;
+
+pub type Env = HashMap<String, String>;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct YarpManifest {
+    // bumped on every breaking change to the manifest, older manifests are migrated in `migrate`
+    pub version: u32,
+    pub loads: Vec<Load>,
+    pub libs: Vec<Lib>,
+    pub python: Python,
+    pub env: Env,
+    pub skip: Skip,
+
+    // not written by `yarp.discover`, users add these by hand (or pass them to export in a side file)
+    #[serde(default)]
+    pub filters: Filters,
+
+    // globs of "plug and play" libraries whose dependencies may be missing (numba's tbbpool without TBB, say)
+    // a glob without `/` matches the file name, otherwise the whole path
+    // such libraries are exported as is (unpatched) if their dependencies cannot be found, instead of failing
+    #[serde(default)]
+    pub optional: Vec<String>,
+
+    // top-level modules imported at discovery, used by `export --prune`, older manifests don't have it
+    #[serde(default)]
+    pub imports: Option<Vec<String>>,
+
+    // platform and architecture of the discovered python, older manifests don't have it and are analysed for the host
+    #[serde(default)]
+    pub target: Option<Target>,
+}
+
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Skip {
+    pub prefixes: Vec<PathBuf>,
+    pub libs: Vec<String>,
+}
+
+/// include/exclude globs for the files walked in stdlib, lib-dynload and site-packages
+/// globs are matched against the path relative to the root, like `numpy/tests/test_core.py`
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct Filters {
+    #[serde(default)]
+    pub stdlib: FileFilter,
+
+    #[serde(default)]
+    pub lib_dynload: FileFilter,
+
+    // applied to every site-packages
+    #[serde(default)]
+    pub site_packages: FileFilter,
+
+    // applied to a single site-packages (in addition to `site_packages`), keyed by its path in `sys.path`
+    #[serde(default)]
+    pub roots: HashMap<PathBuf, FileFilter>,
+}
+
+/// a file is kept if it matches any `include` glob (or `include` is empty), and it matches no `exclude` glob
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct FileFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl Filters {
+    /// add all globs of `other` to ours
+    pub fn extend(&mut self, other: Filters) {
+        self.stdlib.extend(other.stdlib);
+        self.lib_dynload.extend(other.lib_dynload);
+        self.site_packages.extend(other.site_packages);
+        for (root, filter) in other.roots {
+            self.roots.entry(root).or_default().extend(filter);
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn extend(&mut self, other: FileFilter) {
+        for g in other.include {
+            if !self.include.contains(&g) {
+                self.include.push(g);
+            }
+        }
+        for g in other.exclude {
+            if !self.exclude.contains(&g) {
+                self.exclude.push(g);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadKind {
+    Extension,
+    Dlopen,
+}
+
+/// these are the ones which are dlopen-ed
+/// they would be kept in ld-library-path
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Load {
+    pub kind: LoadKind,
+    pub path: PathBuf,
+    pub symlinks: Vec<String>,
+}
+
+/// only dependent libraries, only kept in reals and their symlink farms are created, but not kept in path
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Lib {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Python {
+    pub sys: Sys,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Sys {
+    pub prefix: PathBuf,
+    pub exec_prefix: PathBuf,
+    pub platlibdir: PathBuf,
+    pub version: Version,
+    pub path: Vec<PathBuf>,
+    pub executable: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub abi_thread: String,
+}
+
+impl Version {
+    pub fn get_python_version(&self) -> String {
+        format!("python{}.{}{}", self.major, self.minor, self.abi_thread)
+    }
+}
+
+/// read the manifest at `path`, all `sys.path` entries are normalized
+/// older manifest versions are migrated to `MANIFEST_VERSION` before deserializing
+pub fn read_manifest(path: &PathBuf) -> Result<Box<YarpManifest>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| invalid_manifest(path, e))?;
+    let mut manifest = parse_manifest(&contents).map_err(|reason| invalid_manifest(path, reason))?;
+
+    let problems = validate::validate(&manifest);
+    for warning in &problems.warnings {
+        warn!("manifest at path={}: {}", path.display(), warning);
+    }
+    if !problems.errors.is_empty() {
+        return Err(Error::new(Failures {
+            context: format!("yarp manifest is invalid, path={}", path.display()),
+            errors: problems
+                .errors
+                .into_iter()
+                .map(|reason| invalid_manifest(path, reason))
+                .collect(),
+        }));
+    }
+
+    manifest.python.sys.path = manifest
+        .python
+        .sys
+        .path
+        .iter()
+        .map(|p| normalize_path(p))
+        .collect();
+    Ok(Box::new(manifest))
+}
+
+/// read all manifests and merge them, a single manifest is returned as is
+pub fn read_manifests(paths: &[PathBuf]) -> Result<Box<YarpManifest>> {
+    if let [path] = paths {
+        return read_manifest(path);
+    }
+    let mut manifests = Vec::new();
+    for path in paths {
+        manifests.push((path.clone(), *read_manifest(path)?));
+    }
+    Ok(Box::new(merge_manifests(manifests)?))
+}
+
+/// read a side file with the same shape as `filters` in the manifest
+pub fn read_filters(path: &PathBuf) -> Result<Filters> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| anyhow!("failed in reading filters file, path={}", path.display()))?;
+    let de = &mut serde_json::Deserializer::from_str(&contents);
+    let filters: Filters = serde_path_to_error::deserialize(de).map_err(|e| {
+        anyhow!(
+            "invalid filters file at path={}: {}: {}",
+            path.display(),
+            e.path(),
+            e.inner()
+        )
+    })?;
+    let errors = validate::validate_filters(&filters, "");
+    if !errors.is_empty() {
+        bail!(
+            "invalid filters file at path={}: {}",
+            path.display(),
+            errors.join(", ")
+        );
+    }
+    Ok(filters)
+}
+
+/// parse (and migrate) the contents of a manifest, errors point to the offending field, like `python.sys.path[3]`
+pub fn parse_manifest(contents: &str) -> std::result::Result<YarpManifest, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(contents).map_err(|e| format!("not valid JSON: {}", e))?;
+    let value = migrate::migrate(value)?;
+    serde_path_to_error::deserialize(value).map_err(|e| format!("{}: {}", e.path(), e.inner()))
+}
+
+/// JSON Schema of the current manifest version
+pub fn manifest_schema() -> Schema {
+    schema_for!(YarpManifest)
+}
+
+fn invalid_manifest(path: &PathBuf, e: impl std::fmt::Display) -> Error {
+    Error::new(YarpError::ManifestInvalid {
+        path: path.clone(),
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_deserialize() {
+        let json_str = r#"
+{
+    "version": 1,
+    "loads": [
+        {
+            "kind": "dlopen",
+            "path": "/users/hariomnarang/miniconda3/lib/libpango.so",
+            "symlinks": ["pango"]
+        }
+    ],
+    "libs": [
+        {
+            "path": "some-path"
+        }
+    ],
+    "skip": {
+        "prefixes": [
+            "/miniconda/pygraphviz"
+        ],
+        "libs": []
+    },
+    "python": {
+        "sys": {
+            "prefix": "/Users/hariomnarang/miniconda3",
+            "exec_prefix": "/Users/hariomnarang/miniconda3",
+            "platlibdir": "lib",
+            "version": {
+                "major": 3,
+                "minor": 12,
+                "abi_thread": ""
+            },
+            "path": ["/Users/hariomnarang/miniconda3/lib/python3.12/site-packages"],
+            "executable": "/Users/hariomnarang/miniconda3/bin/python"
+        }
+    },
+    "env": {
+        "PATH": "..."
+    }
+}
+"#;
+
+        let manifest: super::YarpManifest =
+            serde_json::from_str(json_str).expect("Failed to deserialize manifest");
+
+        assert_eq!(manifest.loads.len(), 1);
+        assert_eq!(
+            manifest.loads[0].path.to_str().unwrap(),
+            "/users/hariomnarang/miniconda3/lib/libpango.so"
+        );
+
+        assert_eq!(
+            manifest.python.sys.prefix.to_str().unwrap(),
+            "/Users/hariomnarang/miniconda3"
+        );
+        assert_eq!(
+            manifest.python.sys.exec_prefix.to_str().unwrap(),
+            "/Users/hariomnarang/miniconda3"
+        );
+        assert_eq!(manifest.python.sys.platlibdir.to_str().unwrap(), "lib");
+        assert_eq!(manifest.python.sys.version.major, 3);
+        assert_eq!(manifest.python.sys.version.minor, 12);
+        assert_eq!(manifest.python.sys.version.abi_thread, "");
+        assert_eq!(manifest.python.sys.path.len(), 1);
+        assert_eq!(
+            manifest.python.sys.path[0].to_str().unwrap(),
+            "/Users/hariomnarang/miniconda3/lib/python3.12/site-packages"
+        );
+        assert_eq!(
+            manifest.python.sys.executable.to_str().unwrap(),
+            "/Users/hariomnarang/miniconda3/bin/python"
+        );
+    }
+}
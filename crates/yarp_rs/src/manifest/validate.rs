@@ -0,0 +1,167 @@
+// checks on a deserialized manifest, serde only checks the shape
+// every problem starts with the path of the offending field, like `loads[2].path is not a file`
+
+use std::path::Path;
+
+use globset::Glob;
+
+use super::{FileFilter, Filters, YarpManifest};
+
+#[derive(Debug, Default)]
+pub struct Problems {
+    pub errors: Vec<String>,
+
+    // things which are fine in practice, like a zip in `sys.path` which does not exist
+    pub warnings: Vec<String>,
+}
+
+pub fn validate(manifest: &YarpManifest) -> Problems {
+    let mut problems = Problems::default();
+    let sys = &manifest.python.sys;
+
+    expect_file(&mut problems.errors, "python.sys.executable", &sys.executable);
+    expect_dir(&mut problems.errors, "python.sys.prefix", &sys.prefix);
+    expect_dir(&mut problems.errors, "python.sys.exec_prefix", &sys.exec_prefix);
+    for (i, p) in sys.path.iter().enumerate() {
+        if !p.exists() {
+            problems
+                .warnings
+                .push(format!("python.sys.path[{}] does not exist, path={}", i, p.display()));
+        }
+    }
+
+    for (i, load) in manifest.loads.iter().enumerate() {
+        expect_file(&mut problems.errors, &format!("loads[{}].path", i), &load.path);
+        for (j, symlink) in load.symlinks.iter().enumerate() {
+            if symlink.is_empty() || symlink.contains('/') {
+                problems.errors.push(format!(
+                    "loads[{}].symlinks[{}] must be a file name, found {:?}",
+                    i, j, symlink
+                ));
+            }
+        }
+    }
+    for (i, lib) in manifest.libs.iter().enumerate() {
+        expect_file(&mut problems.errors, &format!("libs[{}].path", i), &lib.path);
+    }
+    for (i, p) in manifest.skip.prefixes.iter().enumerate() {
+        if !p.is_absolute() {
+            problems
+                .errors
+                .push(format!("skip.prefixes[{}] is not absolute, path={}", i, p.display()));
+        }
+    }
+    problems
+        .errors
+        .extend(validate_filters(&manifest.filters, "filters."));
+    for (i, g) in manifest.optional.iter().enumerate() {
+        if let Err(e) = Glob::new(g) {
+            problems
+                .errors
+                .push(format!("optional[{}] is not a valid glob: {}", i, e));
+        }
+    }
+    problems
+}
+
+/// every glob must compile, `prefix` is prepended to the field paths
+pub fn validate_filters(filters: &Filters, prefix: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_filter(&mut errors, &format!("{}stdlib", prefix), &filters.stdlib);
+    validate_filter(&mut errors, &format!("{}lib_dynload", prefix), &filters.lib_dynload);
+    validate_filter(&mut errors, &format!("{}site_packages", prefix), &filters.site_packages);
+    for (root, filter) in &filters.roots {
+        validate_filter(
+            &mut errors,
+            &format!("{}roots[{:?}]", prefix, root.display().to_string()),
+            filter,
+        );
+    }
+    errors
+}
+
+fn validate_filter(errors: &mut Vec<String>, field: &str, filter: &FileFilter) {
+    for (kind, globs) in [("include", &filter.include), ("exclude", &filter.exclude)] {
+        for (i, g) in globs.iter().enumerate() {
+            if let Err(e) = Glob::new(g) {
+                errors.push(format!("{}.{}[{}] is not a valid glob: {}", field, kind, i, e));
+            }
+        }
+    }
+}
+
+fn expect_file(errors: &mut Vec<String>, field: &str, p: &Path) {
+    if !p.exists() {
+        errors.push(format!("{} does not exist, path={}", field, p.display()));
+    } else if !p.is_file() {
+        errors.push(format!("{} is not a file, path={}", field, p.display()));
+    }
+}
+
+fn expect_dir(errors: &mut Vec<String>, field: &str, p: &Path) {
+    if !p.is_dir() {
+        errors.push(format!("{} is not a directory, path={}", field, p.display()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use crate::manifest::parse_manifest;
+
+    use super::validate;
+
+    #[test]
+    fn test_validate_reports_field_paths() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let executable = root.join("bin/python");
+        fs::create_dir_all(executable.parent().unwrap()).unwrap();
+        fs::write(&executable, "").unwrap();
+        fs::create_dir_all(root.join("lib/libfoo.so")).unwrap();
+
+        let manifest = json!({
+            "version": 1,
+            "loads": [
+                {"kind": "dlopen", "path": root.join("lib/libfoo.so"), "symlinks": ["a/b"]}
+            ],
+            "libs": [{"path": root.join("lib/libmissing.so")}],
+            "python": {"sys": {
+                "prefix": root,
+                "exec_prefix": root,
+                "platlibdir": "lib",
+                "version": {"major": 3, "minor": 12, "abi_thread": ""},
+                "path": [root.join("lib/python312.zip")],
+                "executable": executable,
+            }},
+            "env": {},
+            "skip": {"prefixes": ["relative"], "libs": []},
+            "filters": {"stdlib": {"exclude": ["idlelib/**", "a/{b"]}}
+        });
+        let manifest = parse_manifest(&manifest.to_string()).unwrap();
+        let problems = validate(&manifest);
+        let errors = problems.errors.join("\n");
+        assert_eq!(problems.errors.len(), 5, "{}", errors);
+        assert!(errors.contains("filters.stdlib.exclude[1] is not a valid glob"));
+        assert!(errors.contains("loads[0].path is not a file"));
+        assert!(errors.contains("loads[0].symlinks[0] must be a file name"));
+        assert!(errors.contains("libs[0].path does not exist"));
+        assert!(errors.contains("skip.prefixes[0] is not absolute"));
+        assert_eq!(problems.warnings.len(), 1);
+        assert!(problems.warnings[0].starts_with("python.sys.path[0] does not exist"));
+    }
+
+    #[test]
+    fn test_parse_error_has_field_path() {
+        let manifest = json!({
+            "loads": [],
+            "libs": [{"path": 1}],
+        });
+        let err = parse_manifest(&manifest.to_string()).unwrap_err();
+        assert!(err.starts_with("libs[0].path:"), "{}", err);
+    }
+}
@@ -5,7 +5,8 @@ use anyhow::Result;
 
 use crate::parse::BinaryParseError;
 
-use crate::parse::Binary;
+use crate::parse::{Binary, KnownLibs, Resolution};
+use crate::target::Target;
 
 #[derive(Debug, Clone)]
 pub enum Deps {
@@ -24,7 +25,8 @@ impl Deps {
         executable_path: &PathBuf,
         cwd: &PathBuf,
         env: &HashMap<String, String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
+        target: Target,
     ) -> Result<Deps> {
         let bin = crate::parse::parse_and_search(
             path,
@@ -33,6 +35,7 @@ impl Deps {
             env,
             known_libs,
             &Vec::new(),
+            target,
         );
         match bin {
             Ok(bin) => Ok(Deps::Binary(bin)),
@@ -66,12 +69,71 @@ impl Deps {
         }
     }
 
+    /// the dependencies in `find` which are only linked, not exported before us (upward Mach-O dylibs)
+    pub fn upward(&self) -> Vec<PathBuf> {
+        match &self {
+            Deps::Binary(binary) => binary.upward_dependencies(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// the string each dependency was asked for with (DT_NEEDED or load command), along with its resolved path
+    pub fn needed(&self) -> Vec<(String, PathBuf)> {
+        match &self {
+            Deps::Plain => Vec::new(),
+            Deps::Binary(binary) => binary.needed(),
+            #[cfg(test)]
+            Deps::Mock { paths } => paths
+                .iter()
+                .map(|p| {
+                    let name = p.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    (name, p.clone())
+                })
+                .collect(),
+        }
+    }
+
+    pub fn soname(&self) -> Option<String> {
+        match &self {
+            Deps::Binary(binary) => binary.soname(),
+            _ => None,
+        }
+    }
+
+    pub fn new_soname(&self) -> Option<&str> {
+        match &self {
+            Deps::Binary(binary) => binary.new_soname(),
+            _ => None,
+        }
+    }
+
+    pub fn set_new_soname(&mut self, name: &str) {
+        if let Deps::Binary(binary) = self {
+            binary.set_new_soname(name);
+        }
+    }
+
+    pub fn rename_needed(&mut self, dep: &PathBuf, name: &str) {
+        if let Deps::Binary(binary) = self {
+            binary.rename_needed(dep, name);
+        }
+    }
+
+    /// how the dependency asked for with `name` was resolved, `None` for mocks and plain files
+    pub fn resolution(&self, name: &str) -> Option<&Resolution> {
+        match &self {
+            Deps::Binary(binary) => binary.resolution(name),
+            _ => None,
+        }
+    }
+
     pub fn from_path(
         path: &PathBuf,
         executable_path: &PathBuf,
         cwd: &PathBuf,
         env: &HashMap<String, String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
+        target: Target,
     ) -> Result<Deps> {
         let ext = path.extension();
         match ext {
@@ -84,6 +146,7 @@ impl Deps {
                         cwd,
                         env,
                         known_libs,
+                        target,
                     )?)
                 } else {
                     Ok(Deps::Plain)
@@ -109,7 +172,7 @@ impl Deps {
 mod test {
     use std::{collections::HashMap, path::PathBuf};
 
-    use crate::node::deps::Deps;
+    use crate::{node::deps::Deps, parse::KnownLibs, target::Target};
 
     // todo: this only works on my machine
     #[test]
@@ -118,9 +181,10 @@ mod test {
             PathBuf::from("/Users/hariomnarang/miniconda3/envs/platform/lib/libpango-1.0.0.dylib");
         let executable_path = PathBuf::from("/Users/hariomnarang/miniconda3/bin/python");
         let env = HashMap::new();
-        let known_libs = HashMap::new();
+        let known_libs = KnownLibs::default();
         let cwd = PathBuf::from(".");
-        let dylib = Deps::new_binary(&path, &executable_path, &cwd, &env, &known_libs).unwrap();
+        let target = Target::host().unwrap();
+        let dylib = Deps::new_binary(&path, &executable_path, &cwd, &env, &known_libs, target).unwrap();
         let dylib = dylib.find().unwrap();
         dbg!(dylib);
     }
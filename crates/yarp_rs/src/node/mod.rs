@@ -64,6 +64,53 @@ pub enum Pkg {
 }
 
 impl Pkg {
+    // name of the variant, used in reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Pkg::SitePackagesPlain { .. } => "SitePackagesPlain",
+            Pkg::SitePackagesBinary { .. } => "SitePackagesBinary",
+            Pkg::ExecPrefixPlain(_) => "ExecPrefixPlain",
+            Pkg::ExecPrefixBinary(_) => "ExecPrefixBinary",
+            Pkg::PrefixPlain(_) => "PrefixPlain",
+            Pkg::PrefixBinary(_) => "PrefixBinary",
+            Pkg::Executable => "Executable",
+            Pkg::Binary { .. } => "Binary",
+            Pkg::BinaryInLDPath { .. } => "BinaryInLDPath",
+            Pkg::Plain => "Plain",
+        }
+    }
+
+    // the sha of binary variants, plain files are not hashed
+    pub fn sha(&self) -> Option<&str> {
+        match self {
+            Pkg::SitePackagesBinary { sha, .. }
+            | Pkg::Binary { sha }
+            | Pkg::BinaryInLDPath { sha, .. } => Some(sha),
+            Pkg::ExecPrefixBinary(pkg) | Pkg::PrefixBinary(pkg) => Some(&pkg.sha),
+            Pkg::SitePackagesPlain { .. }
+            | Pkg::ExecPrefixPlain(_)
+            | Pkg::PrefixPlain(_)
+            | Pkg::Executable
+            | Pkg::Plain => None,
+        }
+    }
+
+    /// the sha names the reals and symlink farm of a binary, plain files have none and are left as is
+    /// a copy of a binary which resolves to other dependencies than its identical copies is given a sha of its own
+    pub fn set_sha(&mut self, new: String) {
+        match self {
+            Pkg::SitePackagesBinary { sha, .. }
+            | Pkg::Binary { sha }
+            | Pkg::BinaryInLDPath { sha, .. } => *sha = new,
+            Pkg::ExecPrefixBinary(pkg) | Pkg::PrefixBinary(pkg) => pkg.sha = new,
+            Pkg::SitePackagesPlain { .. }
+            | Pkg::ExecPrefixPlain(_)
+            | Pkg::PrefixPlain(_)
+            | Pkg::Executable
+            | Pkg::Plain => {}
+        }
+    }
+
     pub fn from_path(path: &PathBuf) -> Result<Pkg> {
         // uses simple heuristics to find the packager for a path
         // it would be either of binary or plain, as we don't have context of any site-packages
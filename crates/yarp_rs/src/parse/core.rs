@@ -2,11 +2,16 @@ use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 
+use log::{debug, info};
+
 #[derive(Debug, Clone)]
 pub struct Macho {
     // all load commands, along with the resolved path of the dependency
     pub load_cmds: HashMap<String, PathBuf>,
 
+    // how each load command was resolved
+    pub resolutions: HashMap<String, Resolution>,
+
     // all rpaths, along with resolved rpath
     pub rpaths: HashMap<String, PathBuf>,
 
@@ -18,13 +23,64 @@ pub struct Macho {
 
     // all rpaths, unresolved, raw rpaths
     pub all_rpaths: Vec<String>,
+
+    // the kind of each load command in `load_cmds`
+    pub kinds: HashMap<String, DylibKind>,
+
+    // LC_LOAD_WEAK_DYLIB commands which could not be resolved, dyld skips these at runtime
+    pub missing_weak: Vec<String>,
+}
+
+impl Macho {
+    /// resolved rpaths, in the order of the LC_RPATH commands
+    pub fn ordered_rpaths(&self) -> Vec<PathBuf> {
+        self.all_rpaths.iter().filter_map(|r| self.rpaths.get(r).cloned()).collect()
+    }
+}
+
+/// the load command a Mach-O depends on a dylib with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DylibKind {
+    // LC_LOAD_DYLIB
+    Load,
+
+    // LC_LOAD_WEAK_DYLIB, the binary still loads if the dylib is missing
+    Weak,
+
+    // LC_REEXPORT_DYLIB, the symbols of the dylib are exported to our dependents as ours
+    Reexport,
+
+    // LC_LAZY_LOAD_DYLIB, loaded on the first call into it
+    Lazy,
+
+    // LC_LOAD_UPWARD_DYLIB, the dylib depends on us too, used for cycles between libraries of a framework
+    Upward,
+}
+
+impl fmt::Display for DylibKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DylibKind::Load => "LC_LOAD_DYLIB",
+            DylibKind::Weak => "LC_LOAD_WEAK_DYLIB",
+            DylibKind::Reexport => "LC_REEXPORT_DYLIB",
+            DylibKind::Lazy => "LC_LAZY_LOAD_DYLIB",
+            DylibKind::Upward => "LC_LOAD_UPWARD_DYLIB",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Elf {
+    // DT_SONAME, the file name if the library does not have one
+    pub soname: String,
+
     // parsed and found libraries that the elf file needs, equivalent to load_commands
     pub dt_needed: HashMap<String, PathBuf>,
 
+    // how each DT_NEEDED entry was resolved
+    pub resolutions: HashMap<String, Resolution>,
+
     // all resolvable rpaths
     pub dt_rpaths: HashMap<String, PathBuf>,
 
@@ -39,6 +95,115 @@ pub struct Elf {
 
     // all runpath entries (resolved and unresolved)
     pub all_dt_runpaths: Vec<String>,
+
+    // the SONAME this library is exported with, set only when it collides with another library of the same name
+    pub new_soname: Option<String>,
+
+    // DT_NEEDED entries of renamed dependencies, to the name the dependency is exported with
+    pub renamed_needed: HashMap<String, String>,
+}
+
+/// where the dynamic loader (or our fallbacks) found a dependency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchStep {
+    // the needed name is itself a path
+    Path,
+    LdPreload,
+    DtRpath,
+    // DT_RPATH of a binary earlier in the load chain
+    InheritedRpath,
+    // an LC_RPATH of an image earlier in the chain of loaders
+    InheritedLcRpath,
+    LdLibraryPath,
+    DtRunpath,
+    Ldconfig,
+    // /lib64, /lib, /usr/lib64 and /usr/lib
+    DefaultDirs,
+    Ldd,
+
+    Rpath,
+    LoaderPath,
+    ExecutablePath,
+    // an absolute install name
+    Absolute,
+    // a relative install name, resolved from the current directory
+    Cwd,
+    DyldLibraryPath,
+    DyldFallbackLibraryPath,
+    DyldFrameworkPath,
+    DyldFallbackFrameworkPath,
+
+    // a library found in another pass over the environment, not by searching
+    KnownLibs,
+}
+
+impl fmt::Display for SearchStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SearchStep::Path => "path",
+            SearchStep::LdPreload => "LD_PRELOAD",
+            SearchStep::DtRpath => "DT_RPATH",
+            SearchStep::InheritedRpath => "inherited DT_RPATH",
+            SearchStep::InheritedLcRpath => "inherited LC_RPATH",
+            SearchStep::LdLibraryPath => "LD_LIBRARY_PATH",
+            SearchStep::DtRunpath => "DT_RUNPATH",
+            SearchStep::Ldconfig => "ldconfig",
+            SearchStep::DefaultDirs => "default dirs",
+            SearchStep::Ldd => "ldd fallback",
+            SearchStep::Rpath => "@rpath",
+            SearchStep::LoaderPath => "@loader_path",
+            SearchStep::ExecutablePath => "@executable_path",
+            SearchStep::Absolute => "absolute",
+            SearchStep::Cwd => "cwd",
+            SearchStep::DyldLibraryPath => "DYLD_LIBRARY_PATH",
+            SearchStep::DyldFallbackLibraryPath => "DYLD_FALLBACK_LIBRARY_PATH",
+            SearchStep::DyldFrameworkPath => "DYLD_FRAMEWORK_PATH",
+            SearchStep::DyldFallbackFrameworkPath => "DYLD_FALLBACK_FRAMEWORK_PATH",
+            SearchStep::KnownLibs => "known_libs",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// how a single dependency was found
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub step: SearchStep,
+
+    // every candidate path checked before (and including) the winner, in order
+    // ldconfig, ldd and known_libs don't check any paths of ours
+    pub tried: Vec<PathBuf>,
+
+    pub winner: PathBuf,
+}
+
+impl Resolution {
+    /// found without the loader's own search rules, these are the ones worth auditing
+    pub fn needs_audit(&self) -> bool {
+        matches!(self.step, SearchStep::KnownLibs | SearchStep::Ldd)
+    }
+
+    pub fn log(&self, name: &str, object_path: &PathBuf) {
+        if self.needs_audit() {
+            info!(
+                "resolved {} using {} for path={}, winner={} tried={:?}",
+                name,
+                self.step,
+                object_path.display(),
+                self.winner.display(),
+                self.tried
+            );
+        } else {
+            debug!(
+                "resolved {} using {} for path={}, winner={} tried={:?}",
+                name,
+                self.step,
+                object_path.display(),
+                self.winner.display(),
+                self.tried
+            );
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -76,10 +241,80 @@ impl Binary {
         }
     }
 
+    /// dependencies loaded with LC_LOAD_UPWARD_DYLIB, they usually depend on us too and don't decide the export order
+    pub fn upward_dependencies(&self) -> Vec<PathBuf> {
+        match self {
+            Binary::Macho(macho) => macho
+                .load_cmds
+                .iter()
+                .filter(|(cmd, _)| macho.kinds.get(*cmd) == Some(&DylibKind::Upward))
+                .map(|(_, path)| path.clone())
+                .collect(),
+            Binary::Elf(_) => Vec::new(),
+        }
+    }
+
+    /// the DT_NEEDED (or load command) string of every dependency, along with the path it resolved to, sorted by the string
+    pub fn needed(&self) -> Vec<(String, PathBuf)> {
+        let mut needed: Vec<(String, PathBuf)> = match self {
+            Binary::Macho(macho) => macho.load_cmds.clone().into_iter().collect(),
+            Binary::Elf(elf) => elf.dt_needed.clone().into_iter().collect(),
+        };
+        needed.sort();
+        needed
+    }
+
+    /// the name other binaries ask for this one with, DT_SONAME on linux and the file name of the LC_ID_DYLIB on macos
+    pub fn soname(&self) -> Option<String> {
+        match self {
+            Binary::Macho(macho) => macho.id_dylib.as_ref().and_then(|id| {
+                PathBuf::from(id)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+            }),
+            Binary::Elf(elf) => Some(elf.soname.clone()),
+        }
+    }
+
+    /// how the dependency asked for with `name` was resolved
+    pub fn resolution(&self, name: &str) -> Option<&Resolution> {
+        match self {
+            Binary::Macho(macho) => macho.resolutions.get(name),
+            Binary::Elf(elf) => elf.resolutions.get(name),
+        }
+    }
+
+    /// the name this library is exported with, if it was renamed to avoid a SONAME collision
+    pub fn new_soname(&self) -> Option<&str> {
+        match self {
+            Binary::Macho(_) => None,
+            Binary::Elf(elf) => elf.new_soname.as_deref(),
+        }
+    }
+
+    /// export this library as `name`, only ELF libraries are renamed, dyld has no SONAME collisions
+    pub fn set_new_soname(&mut self, name: &str) {
+        if let Binary::Elf(elf) = self {
+            elf.new_soname = Some(name.to_string());
+        }
+    }
+
+    /// every dependency which resolved to `dep` is asked for as `name` after export
+    pub fn rename_needed(&mut self, dep: &PathBuf, name: &str) {
+        if let Binary::Elf(elf) = self {
+            for (needed, path) in &elf.dt_needed {
+                if path == dep {
+                    elf.renamed_needed.insert(needed.clone(), name.to_string());
+                }
+            }
+        }
+    }
+
     pub fn paths_to_add_for_next_search(&self) -> Vec<PathBuf> {
         // DT_RPATH in linux requires us to use the current RPATH the dependencies search space too
+        // dyld does the same for LC_RPATH, @rpath is searched in the rpaths of every image in the chain of loaders
         match self {
-            Binary::Macho(_) => Vec::new(),
+            Binary::Macho(macho) => macho.ordered_rpaths(),
             Binary::Elf(elf) => elf.dt_rpaths.values().cloned().collect(),
         }
     }
@@ -1,9 +1,9 @@
 use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Error, Result, anyhow};
 use lief::elf::{Binary, DynamicEntries};
 
-use crate::{parse::{search::linux::parse_linux_rpath, Elf}, paths::split_colon_separated_into_valid_search_paths};
+use crate::{error::YarpError, parse::{core::{Resolution, SearchStep}, search::linux::parse_linux_rpath, Elf, KnownLibs}, paths::split_colon_separated_into_valid_search_paths};
 
 pub fn parse(
     binary: Binary,
@@ -11,12 +11,13 @@ pub fn parse(
     cwd: &PathBuf,
     env: &HashMap<String, String>,
     extra_rpaths: &Vec<PathBuf>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Elf> {
     let ld_preload = split_colon_separated_into_valid_search_paths(env.get("LD_PRELOAD"));
     let ld_library_path = split_colon_separated_into_valid_search_paths(env.get("LD_LIBRARY_PATH"));
-    let (rpaths, runpaths, libs_needed, _soname) = get_dynamic_entries(&binary, object_path)?;
+    let (rpaths, runpaths, libs_needed, soname) = get_dynamic_entries(&binary, object_path)?;
     do_parse(
+        soname,
         rpaths,
         runpaths,
         libs_needed,
@@ -30,6 +31,7 @@ pub fn parse(
 }
 
 fn do_parse(
+    soname: String,
     rpaths: Vec<String>,
     runpaths: Vec<String>,
     libs_needed: Vec<String>,
@@ -38,7 +40,7 @@ fn do_parse(
     ld_preload: &Vec<PathBuf>,
     ld_library_path: &Vec<PathBuf>,
     extra_rpaths: &Vec<PathBuf>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Elf> {
     let dt_rpaths = resolve_rpaths(&rpaths, object_path)?;
     let dt_runpaths = resolve_rpaths(&runpaths, object_path)?;
@@ -47,9 +49,11 @@ fn do_parse(
     let dt_runpath_bufs: Vec<PathBuf> = dt_runpaths.values().cloned().collect();
 
     let mut dt_needed: HashMap<String, PathBuf> = HashMap::new();
+    let mut resolutions: HashMap<String, Resolution> = HashMap::new();
 
     for lib in &libs_needed {
-        match crate::parse::search::linux::search(
+        let mut tried = Vec::new();
+        let found = crate::parse::search::linux::search(
             lib,
             &dt_rpath_bufs,
             extra_rpaths,
@@ -58,31 +62,38 @@ fn do_parse(
             &ld_library_path,
             cwd,
             object_path,
-        ) {
-            None => {
-                if let Some(known_path) = known_libs.get(lib) {
-                    dt_needed.insert(lib.to_string(), known_path.clone());
-                } else {
-                    bail!(
-                        "failed in finding dependency {} for library at path={}",
-                        lib,
-                        object_path.display()
-                    );
+            &mut tried,
+        );
+        let (winner, step) = match found {
+            Some(found) => found,
+            None => match known_libs.find(lib, object_path) {
+                Some(known_path) => (known_path, SearchStep::KnownLibs),
+                None => {
+                    return Err(Error::new(YarpError::UnresolvedDependency {
+                        name: lib.to_string(),
+                        path: object_path.clone(),
+                        tried,
+                    }));
                 }
-            }
-            Some(path) => {
-                dt_needed.insert(lib.to_string(), path);
-            }
-        }
+            },
+        };
+        let resolution = Resolution { step, tried, winner };
+        resolution.log(lib, object_path);
+        dt_needed.insert(lib.to_string(), resolution.winner.clone());
+        resolutions.insert(lib.to_string(), resolution);
     }
 
     let elf = Elf {
+        soname,
         dt_needed,
+        resolutions,
         dt_rpaths,
         dt_runpaths,
         path: object_path.clone(),
         all_dt_rpaths: rpaths,
         all_dt_runpaths: runpaths,
+        new_soname: None,
+        renamed_needed: HashMap::new(),
     };
 
     Ok(elf)
@@ -0,0 +1,229 @@
+// libraries we know of by name, used when a dependency can't be found using the platform's search rules
+// a name can have candidates in many places (torch and scikit-learn both vendor a libgomp), so a lookup is scoped
+// to the binary asking for it: its own distribution first, then its site-packages, then its prefix, and only then anywhere
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use log::warn;
+
+/// what a binary (or a candidate) belongs to, narrower scopes are preferred
+#[derive(Debug, Clone, Default)]
+pub struct Scopes {
+    // file to the name of the distribution owning it (from RECORD of its dist-info)
+    pub owners: HashMap<PathBuf, String>,
+
+    pub site_pkgs: Vec<PathBuf>,
+
+    // python prefix and exec prefix
+    pub prefixes: Vec<PathBuf>,
+}
+
+impl Scopes {
+    // 0 for the same distribution, 1 for the same site-packages, 2 for the same prefix, 3 otherwise
+    fn rank(&self, requester: &Path, candidate: &Path) -> usize {
+        let owner = self.owners.get(requester);
+        if owner.is_some() && owner == self.owners.get(candidate) {
+            return 0;
+        }
+        let same_root = |roots: &Vec<PathBuf>| {
+            roots
+                .iter()
+                .filter(|r| requester.starts_with(r))
+                .max_by_key(|r| r.components().count())
+                .map(|r| candidate.starts_with(r))
+                .unwrap_or(false)
+        };
+        if same_root(&self.site_pkgs) {
+            1
+        } else if same_root(&self.prefixes) {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+/// more than one library answers to `name` in the narrowest scope of its requesters, `chosen` is used
+#[derive(Debug, Clone)]
+pub struct Ambiguity {
+    pub name: String,
+    pub chosen: PathBuf,
+    pub others: Vec<PathBuf>,
+    pub requesters: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct KnownLibs {
+    // candidates for every file name or SONAME, the most preferred first
+    by_name: HashMap<String, Vec<PathBuf>>,
+
+    scopes: Scopes,
+
+    // lookups are made from many threads, ambiguities are collected for the summary
+    ambiguous: Mutex<BTreeMap<String, Ambiguity>>,
+}
+
+impl KnownLibs {
+    /// `by_name` has the candidates for every name, in the order they are preferred when scopes don't decide
+    pub fn new(by_name: HashMap<String, Vec<PathBuf>>, scopes: Scopes) -> Self {
+        Self {
+            by_name,
+            scopes,
+            ambiguous: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// the library `requester` most likely means by `name`
+    pub fn find(&self, name: &str, requester: &Path) -> Option<PathBuf> {
+        let candidates = self.by_name.get(name)?;
+        let best = candidates
+            .iter()
+            .map(|c| self.scopes.rank(requester, c))
+            .min()?;
+        let mut tied: Vec<&PathBuf> = Vec::new();
+        for c in candidates {
+            // symlinks to the same file are the same library
+            if self.scopes.rank(requester, c) == best && !tied.iter().any(|t| same_file(t, c)) {
+                tied.push(c);
+            }
+        }
+        let chosen = tied[0].clone();
+        if tied.len() > 1 {
+            self.record_ambiguity(name, requester, &chosen, &tied[1..]);
+        }
+        Some(chosen)
+    }
+
+    fn record_ambiguity(&self, name: &str, requester: &Path, chosen: &PathBuf, others: &[&PathBuf]) {
+        warn!(
+            "ambiguous library {} needed by path={}, using {}, other candidates: {:?}",
+            name,
+            requester.display(),
+            chosen.display(),
+            others
+        );
+        let mut ambiguous = self.ambiguous.lock().expect("ambiguity lock is never poisoned");
+        let entry = ambiguous.entry(name.to_string()).or_insert_with(|| Ambiguity {
+            name: name.to_string(),
+            chosen: chosen.clone(),
+            others: Vec::new(),
+            requesters: Vec::new(),
+        });
+        for other in others {
+            if !entry.others.contains(other) && **other != entry.chosen {
+                entry.others.push((*other).clone());
+            }
+        }
+        entry.others.sort();
+        entry.requesters.push(requester.to_path_buf());
+        entry.requesters.sort();
+    }
+
+    /// every ambiguous lookup made so far, sorted by name
+    pub fn ambiguities(&self) -> Vec<Ambiguity> {
+        self.ambiguous
+            .lock()
+            .expect("ambiguity lock is never poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+pub fn write_ambiguity_summary(ambiguities: &Vec<Ambiguity>, out: &mut impl Write) -> Result<()> {
+    if ambiguities.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "ambiguous libraries ({}), found by name with more than one candidate in the same scope:",
+        ambiguities.len()
+    )?;
+    for a in ambiguities {
+        writeln!(
+            out,
+            "  {}: using {} for {} libraries",
+            a.name,
+            a.chosen.display(),
+            a.requesters.len()
+        )?;
+        for other in &a.others {
+            writeln!(out, "    not used: {}", other.display())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use super::{KnownLibs, Scopes};
+
+    #[test]
+    fn test_find_prefers_narrowest_scope() {
+        let site_pkgs = PathBuf::from("/env/lib/python3.12/site-packages");
+        let torch_gomp = site_pkgs.join("torch/lib/libgomp.so.1");
+        let sklearn_gomp = site_pkgs.join("scikit_learn.libs/libgomp.so.1");
+        let system_gomp = PathBuf::from("/usr/lib/libgomp.so.1");
+        let conda_gomp = PathBuf::from("/env/lib/libgomp.so.1");
+        let other_conda_gomp = PathBuf::from("/env/lib/other/libgomp.so.1");
+
+        let known = KnownLibs::new(
+            HashMap::from([(
+                "libgomp.so.1".to_string(),
+                vec![
+                    system_gomp.clone(),
+                    conda_gomp.clone(),
+                    other_conda_gomp.clone(),
+                    torch_gomp.clone(),
+                    sklearn_gomp.clone(),
+                ],
+            )]),
+            Scopes {
+                owners: HashMap::from([
+                    (site_pkgs.join("torch/_C.so"), "torch".to_string()),
+                    (torch_gomp.clone(), "torch".to_string()),
+                    (site_pkgs.join("sklearn/_openmp.so"), "scikit-learn".to_string()),
+                    (sklearn_gomp.clone(), "scikit-learn".to_string()),
+                ]),
+                site_pkgs: vec![site_pkgs.clone()],
+                prefixes: vec![PathBuf::from("/env")],
+            },
+        );
+        let find = |requester: &Path| known.find("libgomp.so.1", requester);
+
+        // same distribution
+        assert_eq!(find(&site_pkgs.join("torch/_C.so")), Some(torch_gomp.clone()));
+        assert_eq!(find(&site_pkgs.join("sklearn/_openmp.so")), Some(sklearn_gomp.clone()));
+        assert!(known.ambiguities().is_empty());
+
+        // same site-packages, but not owned by either
+        assert_eq!(find(&site_pkgs.join("vendored/x.so")), Some(torch_gomp.clone()));
+        let ambiguities = known.ambiguities();
+        assert_eq!(ambiguities.len(), 1);
+        assert_eq!(ambiguities[0].others, vec![sklearn_gomp.clone()]);
+
+        // same prefix, then anywhere
+        assert_eq!(find(Path::new("/env/bin/python")), Some(conda_gomp));
+        assert_eq!(find(Path::new("/opt/x.so")), Some(system_gomp));
+        assert_eq!(known.find("libmissing.so", Path::new("/opt/x.so")), None);
+    }
+}
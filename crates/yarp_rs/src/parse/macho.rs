@@ -3,9 +3,9 @@
 // loader-path would be simply the current path
 // we also want executable-path as an input
 
-use std::{collections::HashMap, ffi::OsStr, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::{Context, Error, Result, anyhow, bail};
+use anyhow::{Context, Error, Result, anyhow};
 use lief::macho::{
     Binary, Commands, FatBinary,
     commands::{Command, LoadCommandTypes},
@@ -13,22 +13,38 @@ use lief::macho::{
 };
 use log::{debug, warn};
 
-use crate::paths::{is_sys_lib, normalize_path, split_colon_separated_into_valid_search_paths};
+use crate::error::YarpError;
+use crate::paths::{is_sys_lib, normalize_path};
 
-use crate::parse::core::{BinaryParseError, Macho};
+use crate::parse::core::{BinaryParseError, DylibKind, Macho, Resolution, SearchStep};
+use crate::parse::KnownLibs;
+use crate::parse::search::macho::{DyldPaths, expand, search};
+use crate::target::Arch;
 
 #[derive(Debug)]
 struct PathResolverCtx<'a> {
     rpaths: Vec<PathBuf>,
+    // rpaths of the images which loaded this one, the nearest first
+    inherited_rpaths: &'a Vec<PathBuf>,
     loader_path: &'a PathBuf,
     shared_lib_ctx: &'a SharedLibCtx<'a>,
 }
 
+// the dylib load commands of a single macho
+#[derive(Debug, Default)]
+struct LoadCmds {
+    id_dylib: Option<String>,
+    load_cmds: HashMap<String, PathBuf>,
+    kinds: HashMap<String, DylibKind>,
+    resolutions: HashMap<String, Resolution>,
+    missing_weak: Vec<String>,
+}
+
 #[derive(Debug)]
 struct SharedLibCtx<'a> {
     executable_path: &'a PathBuf,
     cwd: &'a PathBuf,
-    dyld_library_path: &'a Vec<PathBuf>,
+    dyld: DyldPaths,
 }
 
 /// parse a macho file and get its dependencies
@@ -41,16 +57,16 @@ pub fn parse(
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
+    inherited_rpaths: &Vec<PathBuf>,
+    arch: Arch,
 ) -> Result<Macho> {
-    let dyld_library_path =
-        &split_colon_separated_into_valid_search_paths(env.get("DYLD_LIBRARY_PATH"));
     let ctx = SharedLibCtx {
         executable_path,
         cwd,
-        dyld_library_path,
+        dyld: DyldPaths::from_env(env),
     };
-    _parse(macho, macho_path, &ctx, known_libs)
+    _parse(macho, macho_path, &ctx, known_libs, inherited_rpaths, arch)
         .with_context(|| anyhow!("failed in parsing macho={} context={:?}", macho_path.display(), ctx))
 }
 
@@ -58,23 +74,25 @@ fn _parse(
     fat: FatBinary,
     macho_path: &PathBuf,
     ctx: &SharedLibCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
+    inherited_rpaths: &Vec<PathBuf>,
+    arch: Arch,
 ) -> Result<Macho> {
-    let host_cpu_type = get_host_cpu_type()?;
+    let target_cpu_type = cpu_type(arch);
 
     for macho in fat.iter() {
         // im going through all binaries inside the fat binary and creating everyone's Macho struct
         // this is extra work, we could just check the host header first, and if its not of our arch, move on
         // only problem is, if I'm calling `header()` before `load_commands()` for binaries, its randomly segfaulting
         // if we call `header()` later, it does not happen
-        let (parsed, cpu_type) = _parse_single_macho(&macho_path, macho, ctx, known_libs)?;
-        if cpu_type == host_cpu_type {
+        let (parsed, cpu_type) = _parse_single_macho(&macho_path, macho, ctx, known_libs, inherited_rpaths)?;
+        if cpu_type == target_cpu_type {
             return Ok(parsed);
         }
     }
     warn!(
-        "No binary found inside FAT Macho Binary for the host architecture, ignoring. path={} arch={:?}",
-        macho_path.display(), host_cpu_type
+        "No binary found inside FAT Macho Binary for the target architecture, ignoring. path={} arch={}",
+        macho_path.display(), arch
     );
     return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
 }
@@ -83,7 +101,8 @@ fn _parse_single_macho(
     macho_path: &PathBuf,
     macho: Binary,
     ctx: &SharedLibCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
+    inherited_rpaths: &Vec<PathBuf>,
 ) -> Result<(Macho, CpuType)> {
     let loader_path = macho_path
         .parent()
@@ -95,19 +114,19 @@ fn _parse_single_macho(
 
     let (rpaths, all_rpaths) = get_rpaths(
         &macho,
+        macho_path,
         ctx.executable_path,
         ctx.cwd,
         &loader_path,
-        &ctx.dyld_library_path,
     )
     .context(anyhow!("failed in parsing rpath"))?;
     let resolver_ctx = PathResolverCtx {
         loader_path: &loader_path,
-        rpaths: rpaths.iter().map(|(_, rpath)| rpath.clone()).collect(),
+        rpaths: all_rpaths.iter().filter_map(|r| rpaths.get(r).cloned()).collect(),
+        inherited_rpaths,
         shared_lib_ctx: ctx,
     };
-    let (id_dylib, load_cmds) = get_load_commands(&macho, &macho_path, &resolver_ctx, known_libs)
-        .context(anyhow!(
+    let cmds = get_load_commands(&macho, &macho_path, &resolver_ctx, known_libs).context(anyhow!(
         "failed in parsing load commands for {}",
         macho_path.display()
     ))?;
@@ -115,34 +134,32 @@ fn _parse_single_macho(
     // NOTE: make sure to always call `header` in the end, if called before `load_commands`, we get random segfaults
     Ok((
         Macho {
-            load_cmds,
+            load_cmds: cmds.load_cmds,
+            resolutions: cmds.resolutions,
             rpaths,
-            id_dylib,
+            id_dylib: cmds.id_dylib,
             path: macho_path.clone(),
             all_rpaths,
+            kinds: cmds.kinds,
+            missing_weak: cmds.missing_weak,
         },
         macho.header().cpu_type(),
     ))
 }
 
-fn get_host_cpu_type() -> Result<CpuType> {
-    let current_arch = std::env::consts::ARCH;
-    match current_arch {
-        "x86_64" => Ok(CpuType::X86_64),
-        "aarch64" => Ok(CpuType::ARM64),
-        _ => bail!(
-            "unsupported host architecture {}, only x86_64 and aarch64 are supported",
-            current_arch
-        ),
+fn cpu_type(arch: Arch) -> CpuType {
+    match arch {
+        Arch::X86_64 => CpuType::X86_64,
+        Arch::Aarch64 => CpuType::ARM64,
     }
 }
 
 fn get_rpaths(
     macho: &lief::macho::Binary,
+    macho_path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     loader_path: &PathBuf,
-    dyld_library_path: &Vec<PathBuf>,
 ) -> Result<(HashMap<String, PathBuf>, Vec<String>)> {
     let mut all_rpaths = Vec::new();
     let mut rpaths = HashMap::new();
@@ -151,7 +168,7 @@ fn get_rpaths(
             Commands::RPath(rpath) => {
                 let val = rpath.path();
                 all_rpaths.push(val.clone());
-                let p = resolve_rpath(&val, executable_path, cwd, loader_path, dyld_library_path)
+                let p = resolve_rpath(&val, macho_path, executable_path, cwd, loader_path)
                     .context(anyhow!("failed in resolving rpath={}", val))?;
                 if let Some(inner) = p {
                     rpaths.insert(val, inner);
@@ -167,179 +184,98 @@ fn get_load_commands(
     macho: &lief::macho::Binary,
     macho_path: &PathBuf,
     ctx: &PathResolverCtx,
-    known_libs: &HashMap<String, PathBuf>,
-) -> Result<(Option<String>, HashMap<String, PathBuf>)> {
-    let mut id_dylib = None;
-    let mut load_cmds = HashMap::new();
+    known_libs: &KnownLibs,
+) -> Result<LoadCmds> {
+    let mut cmds = LoadCmds::default();
     for cmd in macho.commands() {
-        match cmd {
-            Commands::Dylib(dylib) => match dylib.command_type() {
-                LoadCommandTypes::IdDylib => id_dylib = Some(dylib.name()),
-                LoadCommandTypes::LoadDylib => {
-                    let val = dylib.name();
-                    if is_sys_lib(&val) {
-                        debug!(
-                            "skipping system library {} in macho parsing, dependency of {}",
-                            val, macho_path.display()
-                        );
-                        continue;
-                    }
-                    let p = resolve_load_cmd_path_with_dyld_fallback(&val, ctx, known_libs)
-                        .with_context(|| {
-                            format!("failed in resolving load command={} ctx={:?}", val, ctx)
-                        })?;
-                    match p {
-                        Some(p) => {
-                            let p = normalize_path(&p);
-                            load_cmds.insert(val, p);
-                        }
-                        None => match known_libs.get(&val) {
-                            None => {
-                                bail!(
-                                    "could not find dependency for load_cmd={} ctx={:?}",
-                                    val,
-                                    ctx
-                                );
-                            }
-                            Some(lib_path) => {
-                                load_cmds.insert(val, lib_path.clone());
-                            }
-                        },
-                    }
+        let dylib = match cmd {
+            Commands::Dylib(dylib) => dylib,
+            _ => continue,
+        };
+        let kind = match dylib.command_type() {
+            LoadCommandTypes::IdDylib => {
+                cmds.id_dylib = Some(dylib.name());
+                continue;
+            }
+            LoadCommandTypes::LoadDylib => DylibKind::Load,
+            LoadCommandTypes::LoadWeakDylib => DylibKind::Weak,
+            LoadCommandTypes::ReExportDylib => DylibKind::Reexport,
+            LoadCommandTypes::LazyLoadDylib => DylibKind::Lazy,
+            LoadCommandTypes::LoadUpwardDylib => DylibKind::Upward,
+            _ => continue,
+        };
+        let val = dylib.name();
+        if is_sys_lib(&val) {
+            debug!(
+                "skipping system library {} in macho parsing, dependency of {}",
+                val, macho_path.display()
+            );
+            continue;
+        }
+        let mut tried = Vec::new();
+        let found = search(
+            &val,
+            &ctx.rpaths,
+            ctx.inherited_rpaths,
+            ctx.loader_path,
+            ctx.shared_lib_ctx.executable_path,
+            ctx.shared_lib_ctx.cwd,
+            &ctx.shared_lib_ctx.dyld,
+            &mut tried,
+        );
+        let leaf = val.rsplit('/').next().unwrap_or(&val);
+        let (winner, step) = match found {
+            Some((p, step)) => (normalize_path(&p), step),
+            None => match known_libs.find(leaf, macho_path) {
+                None if kind == DylibKind::Weak => {
+                    warn!(
+                        "weak load command={} of path={} could not be resolved, it is not exported and dyld will skip it, tried={:?}",
+                        val,
+                        macho_path.display(),
+                        tried
+                    );
+                    cmds.missing_weak.push(val);
+                    continue;
+                }
+                None => {
+                    return Err(Error::new(YarpError::UnresolvedDependency {
+                        name: val,
+                        path: macho_path.clone(),
+                        tried,
+                    }))
+                    .with_context(|| anyhow!("ctx={:?}", ctx));
                 }
-                _ => {}
+                Some(lib_path) => (lib_path, SearchStep::KnownLibs),
             },
-            _ => {}
         };
+        let resolution = Resolution { step, tried, winner };
+        resolution.log(&val, macho_path);
+        cmds.load_cmds.insert(val.clone(), resolution.winner.clone());
+        cmds.kinds.insert(val.clone(), kind);
+        cmds.resolutions.insert(val, resolution);
     }
-    Ok((id_dylib, load_cmds))
+    Ok(cmds)
 }
 
 fn resolve_rpath(
     load_cmd_rpath: &str,
+    macho_path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     loader_path: &PathBuf,
-    dyld_library_path: &Vec<PathBuf>,
 ) -> Result<Option<PathBuf>> {
     if load_cmd_rpath.starts_with("@rpath/") {
-        bail!(
-            "could not resolve RPath={}, it is invalid for RPaths to start with `@rpath/`, most likely the shared library is corrupted. Raise an issue if you think this is a bug",
-            load_cmd_rpath,
-        );
-    }
-    let ctx = &PathResolverCtx {
-        rpaths: Vec::new(),
-        loader_path: loader_path,
-        shared_lib_ctx: &SharedLibCtx {
-            executable_path: executable_path,
-            cwd: cwd,
-            dyld_library_path,
-        },
-    };
-    resolve_load_cmd_path(load_cmd_rpath, ctx)
-}
-
-fn resolve_load_cmd_path_with_dyld_fallback(
-    load_cmd_path: &str,
-    ctx: &PathResolverCtx,
-    known_libs: &HashMap<String, PathBuf>,
-) -> Result<Option<PathBuf>> {
-    let resolved = resolve_load_cmd_path(load_cmd_path, ctx)?;
-    match resolved {
-        Some(resolved) => Ok(Some(resolved)),
-        None => {
-            let p = PathBuf::from_str(load_cmd_path).context(format!(
-                "failure in converting load_cmd to PathBuf, cmd={}",
-                load_cmd_path
-            ))?;
-            match p.file_name() {
-                None => Ok(None),
-                Some(file_name) => {
-                    let resolved = find_in_dirs(file_name, &ctx.shared_lib_ctx.dyld_library_path)?;
-                    match resolved {
-                        Some(resolved) => Ok(Some(resolved)),
-                        None => {
-                            let file_name = file_name.to_str().with_context(|| {
-                                anyhow!(
-                                    "failed in converting file_name to str, file_name={}",
-                                    file_name.display()
-                                )
-                            })?;
-                            match known_libs.get(file_name) {
-                                Some(lib_path) => Ok(Some(lib_path.clone())),
-                                None => Ok(None),
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
-
-fn find_in_dirs(file_name: &OsStr, dirs: &Vec<PathBuf>) -> Result<Option<PathBuf>> {
-    for dir in dirs {
-        let candidate = dir.join(file_name);
-        if candidate.exists() {
-            return Ok(Some(candidate.clone()));
-        }
-    }
-    Ok(None)
-}
-
-/// given a load command, this function would try to resolve it
-/// if it does not find any path associated with the load command, we send an `Ok(None)`
-/// on every other kind of error, we send Err
-fn resolve_load_cmd_path(load_cmd_path: &str, ctx: &PathResolverCtx) -> Result<Option<PathBuf>> {
-    let load_path = PathBuf::from(load_cmd_path);
-    if load_path.starts_with("@rpath") {
-        let p = load_path.strip_prefix("@rpath").expect(&format!(
-            "fatal: load_cmd_path={} should start with @rpath/",
-            load_cmd_path
-        ));
-        for rpath in &ctx.rpaths {
-            let resolved = rpath.join(p);
-            if resolved.exists() {
-                return Ok(Some(resolved));
-            }
-        }
-        return Ok(None);
-    } else if load_path.starts_with("@loader_path") {
-        let p = load_path.strip_prefix("@loader_path").expect(&format!(
-            "fatal: load_cmd_path={} should start with @loader_path/",
-            load_cmd_path
-        ));
-        let resolved = ctx.loader_path.join(p);
-        if resolved.exists() {
-            return Ok(Some(resolved));
-        } else {
-            return Ok(None);
-        }
-    } else if load_path.starts_with("@executable_path") {
-        let p = load_path.strip_prefix("@executable_path").expect(&format!(
-            "fatal: load_cmd_path={} should start with @executable_path/",
-            load_cmd_path
-        ));
-        let resolved = ctx.shared_lib_ctx.executable_path.join(p);
-        if resolved.exists() {
-            return Ok(Some(resolved));
-        } else {
-            return Ok(None);
-        }
-    } else if load_path.starts_with("/") {
-        let resolved = load_path;
-        if resolved.exists() {
-            return Ok(Some(resolved));
-        } else {
-            return Ok(None);
-        }
-    } else {
-        let resolved = ctx.shared_lib_ctx.cwd.join(load_cmd_path);
-        if resolved.exists() {
-            return Ok(Some(resolved));
-        } else {
-            return Ok(None);
-        }
+        return Err(Error::new(YarpError::CorruptBinary {
+            path: macho_path.clone(),
+            reason: format!(
+                "could not resolve RPath={}, it is invalid for RPaths to start with `@rpath/`. Raise an issue if you think this is a bug",
+                load_cmd_rpath,
+            ),
+        }));
     }
+    // rpaths are not searched for, they are only expanded
+    Ok(expand(load_cmd_rpath, &Vec::new(), &Vec::new(), loader_path, executable_path, cwd)
+        .into_iter()
+        .map(|(p, _)| p)
+        .find(|p| p.exists()))
 }
@@ -1,5 +1,6 @@
 mod core;
 mod elf;
+mod known_libs;
 mod macho;
 mod search;
 
@@ -7,44 +8,47 @@ use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use anyhow::anyhow;
-pub use core::{Binary, BinaryParseError, Elf, Macho};
+pub use known_libs::{Ambiguity, KnownLibs, Scopes, write_ambiguity_summary};
+pub use core::{Binary, BinaryParseError, DylibKind, Elf, Macho, Resolution, SearchStep};
 use std::collections::HashSet;
 use log::warn;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use crate::target::{Os, Target};
+
 use elf::parse as parse_linux;
 use macho::parse as parse_macho;
 // pub use macho::get_deps_from_macho;
 
+/// `target` decides which binaries are understood (ELF for linux, Mach-O for macos) and which slice of a fat Mach-O is used
 pub fn parse_and_search(
     path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     extra_rpaths: &Vec<PathBuf>,
+    target: Target,
 ) -> Result<Binary> {
-    // TODO: take a set instead of doing this, this is very inefficient way of doing this
     let extra_rpaths = &deduplicate_paths(extra_rpaths);
     let mut file =
         std::fs::File::open(path).context(anyhow!("Can't open the file={}", path.display()))?;
-    let os = std::env::consts::OS;
     let binary = match lief::Binary::from(&mut file) {
         Some(lief::Binary::ELF(elf)) => {
-            if os != "linux" {
-                warn!("found an ELF file in non-linux system, path={}", path.display());
+            if target.os != Os::Linux {
+                warn!("found an ELF file for a {} target, path={}", target, path.display());
                 return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
             }
             let elf = parse_linux(elf, path, cwd, env, extra_rpaths, known_libs)?;
             Binary::Elf(elf)
         }
         Some(lief::Binary::MachO(macho)) => {
-            if os != "macos" {
-                warn!("found a MACHO file in non-macos system, path={}", path.display());
+            if target.os != Os::Macos {
+                warn!("found a MACHO file for a {} target, path={}", target, path.display());
                 return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
             }
-            let macho = parse_macho(macho, path, executable_path, cwd, env, known_libs)?;
+            let macho = parse_macho(macho, path, executable_path, cwd, env, known_libs, extra_rpaths, target.arch)?;
             Binary::Macho(macho)
         }
         Some(lief::Binary::PE(_)) => {
@@ -63,10 +67,85 @@ pub fn parse_and_search(
 }
 
 
+// keeps the first of every path, the order of rpaths is the order they are searched in
 fn deduplicate_paths(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
-    let mut set = HashSet::new();
-    for path in paths {
-        set.insert(path.clone());
+    let mut seen = HashSet::new();
+    paths.iter().filter(|p| seen.insert(*p)).cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use anyhow::Result;
+
+    use super::{Binary, BinaryParseError, DylibKind, KnownLibs, parse_and_search};
+
+    // generated by fixtures/macho/gen.py, they parse on any host
+    fn fixture(rel: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/macho").join(rel)
+    }
+
+    fn parse(path: &PathBuf, target: &str) -> Result<Binary> {
+        parse_and_search(
+            path,
+            &PathBuf::from("/env/bin/python"),
+            &fixture(""),
+            &HashMap::new(),
+            &KnownLibs::default(),
+            &Vec::new(),
+            target.parse().unwrap(),
+        )
     }
-    set.into_iter().collect()
-}
\ No newline at end of file
+
+    fn is_unsupported(res: Result<Binary>) -> bool {
+        match res {
+            Err(e) => matches!(
+                e.downcast_ref::<BinaryParseError>(),
+                Some(BinaryParseError::UnsupportedArchitecture)
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_macho_slice_from_target() {
+        let liba = fixture("lib/liba.dylib");
+        let libb = fixture("lib/libb.dylib");
+
+        // the target picks the slice of the fat binary, system libraries are skipped
+        let arm64 = parse(&liba, "macos-aarch64").unwrap();
+        assert_eq!(arm64.needed(), vec![("@rpath/libb.dylib".to_string(), libb.clone())]);
+        assert_eq!(arm64.soname(), Some("liba.dylib".to_string()));
+        let x86_64 = parse(&liba, "macos-x86_64").unwrap();
+        assert_eq!(x86_64.needed(), vec![("@loader_path/libb.dylib".to_string(), libb.clone())]);
+
+        // libb is arm64 only, and a Mach-O is not understood for a linux target
+        assert!(is_unsupported(parse(&libb, "macos-x86_64")));
+        assert!(is_unsupported(parse(&liba, "linux-x86_64")));
+    }
+
+    #[test]
+    fn test_macho_load_command_kinds() {
+        let macho = match parse(&fixture("lib/libkinds.dylib"), "macos-aarch64").unwrap() {
+            Binary::Macho(macho) => macho,
+            _ => panic!("expected a Mach-O"),
+        };
+        let mut kinds: Vec<(String, DylibKind)> = macho.kinds.clone().into_iter().collect();
+        kinds.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            kinds,
+            vec![
+                ("@rpath/libb.dylib".to_string(), DylibKind::Load),
+                ("@rpath/libc.dylib".to_string(), DylibKind::Reexport),
+                ("@rpath/libd.dylib".to_string(), DylibKind::Lazy),
+                ("@rpath/libe.dylib".to_string(), DylibKind::Upward),
+            ]
+        );
+        assert_eq!(macho.load_cmds["@rpath/libc.dylib"], fixture("lib/libc.dylib"));
+
+        // a missing weak dylib is not an error, it is recorded and left out of the dependencies
+        assert_eq!(macho.missing_weak, vec!["@rpath/libmissing.dylib".to_string()]);
+        assert!(!macho.load_cmds.contains_key("@rpath/libmissing.dylib"));
+    }
+}
@@ -8,12 +8,13 @@ use std::path::PathBuf;
 use log::warn;
 
 pub use crate::parse::search::linux::rpath::parse_rpath as parse_linux_rpath;
+use crate::parse::core::SearchStep;
 use crate::paths::to_path_buf;
 
 macro_rules! try_find_in_dirs {
-    ($name:expr, $dirs:expr) => {
-        if let Some(p) = find_in_dirs($name, $dirs) {
-            return Some(p);
+    ($name:expr, $dirs:expr, $tried:expr, $step:expr) => {
+        if let Some(p) = find_in_dirs($name, $dirs, $tried) {
+            return Some((p, $step));
         }
     };
 }
@@ -27,34 +28,36 @@ pub fn search(
     ld_library_path: &Vec<PathBuf>,
     cwd: &PathBuf,
     object_path: &PathBuf,
-) -> Option<PathBuf> {
+    tried: &mut Vec<PathBuf>,
+) -> Option<(PathBuf, SearchStep)> {
     // this function intentionally does not fail
     // we simply log warnings if there is a failure as rpath resolution is always about trying multiple stuff
+    // every candidate path checked on the way is pushed to `tried`, ldconfig and ldd don't tell us what they checked
 
     // search as a path
-    if let Some(path) = search_name_as_path(name, cwd) {
-        return Some(path);
+    if let Some(path) = search_name_as_path(name, cwd, tried) {
+        return Some((path, SearchStep::Path));
     }
 
     // search LD_PRELOAD
-    try_find_in_dirs!(name, &ld_preload);
+    try_find_in_dirs!(name, &ld_preload, tried, SearchStep::LdPreload);
 
     // search DT_RPATH
     let search_rpaths = dt_runpaths.len() == 0;
     if search_rpaths {
-        try_find_in_dirs!(name, dt_rpaths);
-        try_find_in_dirs!(name, extra_rpaths);
+        try_find_in_dirs!(name, dt_rpaths, tried, SearchStep::DtRpath);
+        try_find_in_dirs!(name, extra_rpaths, tried, SearchStep::InheritedRpath);
     }
 
     // search LD_LIBRARY_PATH
-    try_find_in_dirs!(name, &ld_library_path);
+    try_find_in_dirs!(name, &ld_library_path, tried, SearchStep::LdLibraryPath);
 
     // search DT_RUNPATH
-    try_find_in_dirs!(name, dt_runpaths);
+    try_find_in_dirs!(name, dt_runpaths, tried, SearchStep::DtRunpath);
 
     // fallback, ask ldconfig
     if let Ok(path) = ldconfig::find(name) {
-        return Some(path);
+        return Some((path, SearchStep::Ldconfig));
     }
 
     try_find_in_dirs!(
@@ -64,28 +67,27 @@ pub fn search(
             PathBuf::from("/lib"),
             PathBuf::from("/usr/lib64"),
             PathBuf::from("/usr/lib")
-        ]
+        ],
+        tried,
+        SearchStep::DefaultDirs
     );
 
     if let Ok(path) = ldd::find(name, object_path) {
-        return Some(path)
+        return Some((path, SearchStep::Ldd))
     }
 
     None
 }
 
-fn search_name_as_path(name: &str, cwd: &PathBuf) -> Option<PathBuf> {
+fn search_name_as_path(name: &str, cwd: &PathBuf, tried: &mut Vec<PathBuf>) -> Option<PathBuf> {
     if !name.contains("/") {
         None
     } else {
         match to_path_buf(name) {
             Ok(p) => {
-                if p.is_absolute() {
-                    if p.exists() { Some(p) } else { None }
-                } else {
-                    let p = cwd.join(p);
-                    if p.exists() { Some(p) } else { None }
-                }
+                let p = if p.is_absolute() { p } else { cwd.join(p) };
+                tried.push(p.clone());
+                if p.exists() { Some(p) } else { None }
             }
             Err(e) => {
                 warn!(
@@ -98,12 +100,53 @@ fn search_name_as_path(name: &str, cwd: &PathBuf) -> Option<PathBuf> {
     }
 }
 
-fn find_in_dirs(file_name: &str, dirs: &Vec<PathBuf>) -> Option<PathBuf> {
+fn find_in_dirs(file_name: &str, dirs: &Vec<PathBuf>, tried: &mut Vec<PathBuf>) -> Option<PathBuf> {
     for dir in dirs {
         let candidate = dir.join(file_name);
+        tried.push(candidate.clone());
         if candidate.exists() {
             return Some(candidate.clone());
         }
     }
     None
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::parse::core::SearchStep;
+
+    use super::search;
+
+    #[test]
+    fn test_search_records_tried_candidates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ld_library_path = tmp.path().join("ld");
+        let runpath = tmp.path().join("runpath");
+        std::fs::create_dir_all(&ld_library_path).unwrap();
+        std::fs::create_dir_all(&runpath).unwrap();
+        std::fs::File::create(runpath.join("libyarpfoo.so.1")).unwrap();
+
+        let mut tried = Vec::new();
+        let found = search(
+            "libyarpfoo.so.1",
+            &Vec::new(),
+            &Vec::new(),
+            &vec![runpath.clone()],
+            &Vec::new(),
+            &vec![ld_library_path.clone()],
+            &PathBuf::from("/"),
+            &tmp.path().join("libbar.so"),
+            &mut tried,
+        );
+        assert_eq!(
+            found,
+            Some((runpath.join("libyarpfoo.so.1"), SearchStep::DtRunpath))
+        );
+        assert_eq!(
+            tried,
+            vec![ld_library_path.join("libyarpfoo.so.1"), runpath.join("libyarpfoo.so.1")]
+        );
+    }
+}
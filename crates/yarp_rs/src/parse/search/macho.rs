@@ -0,0 +1,289 @@
+// dyld's search for the install name in a load command, see `man dyld` and docs/SEARCH.md
+// the install name itself is expanded first, `@rpath/` stands for one path per rpath
+// the rpaths are the image's own LC_RPATHs, then those of the images which loaded it, back to the executable
+// DYLD_* variables are searched with the leaf name (or the partial path of a framework), before and after the install name
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::parse::core::SearchStep;
+use crate::paths::split_colon_separated_into_valid_search_paths;
+
+macro_rules! try_find_in_dirs {
+    ($name:expr, $dirs:expr, $tried:expr, $step:expr) => {
+        if let Some(p) = find_in_dirs($name, $dirs, $tried) {
+            return Some((p, $step));
+        }
+    };
+}
+
+/// the DYLD_* search paths, the fallbacks have dyld's defaults when they are not set
+#[derive(Debug, Clone, Default)]
+pub struct DyldPaths {
+    pub library_path: Vec<PathBuf>,
+    pub fallback_library_path: Vec<PathBuf>,
+    pub framework_path: Vec<PathBuf>,
+    pub fallback_framework_path: Vec<PathBuf>,
+}
+
+impl DyldPaths {
+    pub fn from_env(env: &HashMap<String, String>) -> DyldPaths {
+        let paths = |var: &str, default: &[&str]| match env.get(var) {
+            Some(val) => split_colon_separated_into_valid_search_paths(Some(val)),
+            None => default.iter().map(PathBuf::from).collect(),
+        };
+        DyldPaths {
+            library_path: paths("DYLD_LIBRARY_PATH", &[]),
+            fallback_library_path: paths("DYLD_FALLBACK_LIBRARY_PATH", &["/usr/local/lib", "/usr/lib"]),
+            framework_path: paths("DYLD_FRAMEWORK_PATH", &[]),
+            fallback_framework_path: paths(
+                "DYLD_FALLBACK_FRAMEWORK_PATH",
+                &["/Library/Frameworks", "/System/Library/Frameworks"],
+            ),
+        }
+    }
+}
+
+/// `loader_path` is the directory of the binary with the load command, `executable_path` the main executable itself
+/// `inherited_rpaths` are the rpaths of the chain of loaders, the nearest loader first
+pub fn search(
+    install_name: &str,
+    rpaths: &Vec<PathBuf>,
+    inherited_rpaths: &Vec<PathBuf>,
+    loader_path: &Path,
+    executable_path: &Path,
+    cwd: &Path,
+    dyld: &DyldPaths,
+    tried: &mut Vec<PathBuf>,
+) -> Option<(PathBuf, SearchStep)> {
+    // like the linux search, this never fails, every candidate checked is pushed to `tried`
+    let leaf = install_name.rsplit('/').next().filter(|l| !l.is_empty())?;
+
+    if !install_name.contains('/') {
+        try_find_in_dirs!(leaf, &dyld.library_path, tried, SearchStep::DyldLibraryPath);
+        try_find_in_dirs!(leaf, rpaths, tried, SearchStep::Rpath);
+        try_find_in_dirs!(leaf, inherited_rpaths, tried, SearchStep::InheritedLcRpath);
+        try_find_in_dirs!(leaf, &vec![cwd.to_path_buf()], tried, SearchStep::Cwd);
+        try_find_in_dirs!(leaf, &dyld.fallback_library_path, tried, SearchStep::DyldFallbackLibraryPath);
+        return None;
+    }
+
+    if let Some(framework) = framework_partial_path(install_name) {
+        try_find_in_dirs!(framework, &dyld.framework_path, tried, SearchStep::DyldFrameworkPath);
+        if let Some(found) = find_as_is(install_name, rpaths, inherited_rpaths, loader_path, executable_path, cwd, tried) {
+            return Some(found);
+        }
+        try_find_in_dirs!(
+            framework,
+            &dyld.fallback_framework_path,
+            tried,
+            SearchStep::DyldFallbackFrameworkPath
+        );
+        return None;
+    }
+
+    try_find_in_dirs!(leaf, &dyld.library_path, tried, SearchStep::DyldLibraryPath);
+    if let Some(found) = find_as_is(install_name, rpaths, inherited_rpaths, loader_path, executable_path, cwd, tried) {
+        return Some(found);
+    }
+    try_find_in_dirs!(leaf, &dyld.fallback_library_path, tried, SearchStep::DyldFallbackLibraryPath);
+    None
+}
+
+/// the paths an install name (or an LC_RPATH) stands for, in the order dyld tries them
+/// `@executable_path/` is the directory of the executable, relative paths are relative to the cwd
+pub fn expand(
+    path: &str,
+    rpaths: &Vec<PathBuf>,
+    inherited_rpaths: &Vec<PathBuf>,
+    loader_path: &Path,
+    executable_path: &Path,
+    cwd: &Path,
+) -> Vec<(PathBuf, SearchStep)> {
+    if let Some(rest) = strip_token(path, "@rpath") {
+        let own = rpaths.iter().map(|r| (join(r, rest), SearchStep::Rpath));
+        let inherited = inherited_rpaths
+            .iter()
+            .map(|r| (join(r, rest), SearchStep::InheritedLcRpath));
+        own.chain(inherited).collect()
+    } else if let Some(rest) = strip_token(path, "@loader_path") {
+        vec![(join(loader_path, rest), SearchStep::LoaderPath)]
+    } else if let Some(rest) = strip_token(path, "@executable_path") {
+        let executable_dir = executable_path.parent().unwrap_or(executable_path);
+        vec![(join(executable_dir, rest), SearchStep::ExecutablePath)]
+    } else if path.starts_with('/') {
+        vec![(PathBuf::from(path), SearchStep::Absolute)]
+    } else {
+        vec![(cwd.join(path), SearchStep::Cwd)]
+    }
+}
+
+fn find_as_is(
+    install_name: &str,
+    rpaths: &Vec<PathBuf>,
+    inherited_rpaths: &Vec<PathBuf>,
+    loader_path: &Path,
+    executable_path: &Path,
+    cwd: &Path,
+    tried: &mut Vec<PathBuf>,
+) -> Option<(PathBuf, SearchStep)> {
+    for (candidate, step) in expand(install_name, rpaths, inherited_rpaths, loader_path, executable_path, cwd) {
+        tried.push(candidate.clone());
+        if candidate.exists() {
+            return Some((candidate, step));
+        }
+    }
+    None
+}
+
+// `@loader_path/../lib` -> `../lib`, and `@loader_path` -> ``
+fn strip_token<'a>(path: &'a str, token: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(token)?;
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix('/')
+    }
+}
+
+fn join(dir: &Path, rest: &str) -> PathBuf {
+    if rest.is_empty() {
+        dir.to_path_buf()
+    } else {
+        dir.join(rest)
+    }
+}
+
+// `/Library/Frameworks/Python.framework/Versions/3.9/Python` -> `Python.framework/Versions/3.9/Python`
+// the leaf has to be the framework's name, optionally with a suffix like `_debug`
+fn framework_partial_path(install_name: &str) -> Option<&str> {
+    let leaf = install_name.rsplit('/').next()?;
+    let mut start = 0;
+    for component in install_name.split('/') {
+        if let Some(name) = component.strip_suffix(".framework")
+            && !name.is_empty()
+            && (leaf == name || leaf.starts_with(&format!("{}_", name)))
+        {
+            return Some(&install_name[start..]);
+        }
+        start += component.len() + 1;
+    }
+    None
+}
+
+fn find_in_dirs(name: &str, dirs: &Vec<PathBuf>, tried: &mut Vec<PathBuf>) -> Option<PathBuf> {
+    for dir in dirs {
+        let candidate = dir.join(name);
+        tried.push(candidate.clone());
+        if candidate.exists() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parse::core::SearchStep;
+
+    use super::{DyldPaths, framework_partial_path, search};
+
+    const TREE: &[&str] = &[
+        "env/bin/python",
+        "env/lib/libpython.dylib",
+        "env/lib/site-packages/pkg/_ext.so",
+        "env/lib/site-packages/pkg/.dylibs/libfoo.dylib",
+        "env/Frameworks/Python.framework/Versions/3.9/Python",
+        "dyld/libfoo.dylib",
+        "dyld_frameworks/Python.framework/Versions/3.9/Python",
+        "fallback/libbar.dylib",
+        "fallback_frameworks/Tk.framework/Versions/8.6/Tk",
+        "usr_local_lib/libbaz.dylib",
+        "cwd/libcwd.dylib",
+    ];
+
+    #[test]
+    fn test_search() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for rel in TREE {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::File::create(path).unwrap();
+        }
+        let p = |rel: &str| root.join(rel);
+
+        let loader_path = p("env/lib/site-packages/pkg");
+        let executable_path = p("env/bin/python");
+        let cwd = p("cwd");
+        let rpaths = vec![p("env/lib/site-packages/pkg/.dylibs")];
+        let inherited_rpaths = vec![p("env/lib")];
+        let no_env = DyldPaths {
+            fallback_library_path: vec![p("fallback"), p("usr_local_lib")],
+            fallback_framework_path: vec![p("fallback_frameworks")],
+            ..Default::default()
+        };
+        let with_env = DyldPaths {
+            library_path: vec![p("dyld")],
+            framework_path: vec![p("dyld_frameworks")],
+            ..no_env.clone()
+        };
+
+        // (install name, DYLD_* paths, expected match)
+        let cases = vec![
+            ("@rpath/libfoo.dylib", &no_env, Some((p("env/lib/site-packages/pkg/.dylibs/libfoo.dylib"), SearchStep::Rpath))),
+            // the rpaths of the loaders are searched after our own
+            ("@rpath/libpython.dylib", &no_env, Some((p("env/lib/libpython.dylib"), SearchStep::InheritedLcRpath))),
+            // DYLD_LIBRARY_PATH is searched with the leaf before the install name
+            ("@rpath/libfoo.dylib", &with_env, Some((p("dyld/libfoo.dylib"), SearchStep::DyldLibraryPath))),
+            ("@loader_path/.dylibs/libfoo.dylib", &no_env, Some((p("env/lib/site-packages/pkg/.dylibs/libfoo.dylib"), SearchStep::LoaderPath))),
+            // relative to the directory of the executable, not the executable
+            ("@executable_path/../lib/libpython.dylib", &no_env, Some((p("env/bin/../lib/libpython.dylib"), SearchStep::ExecutablePath))),
+            ("libcwd.dylib", &no_env, Some((p("cwd/libcwd.dylib"), SearchStep::Cwd))),
+            ("libpython.dylib", &no_env, Some((p("env/lib/libpython.dylib"), SearchStep::InheritedLcRpath))),
+            // the leaf is looked up in the fallbacks, /usr/local/lib is one of the defaults
+            ("/opt/gone/libbar.dylib", &no_env, Some((p("fallback/libbar.dylib"), SearchStep::DyldFallbackLibraryPath))),
+            ("@rpath/libbaz.dylib", &no_env, Some((p("usr_local_lib/libbaz.dylib"), SearchStep::DyldFallbackLibraryPath))),
+            ("libbaz.dylib", &no_env, Some((p("usr_local_lib/libbaz.dylib"), SearchStep::DyldFallbackLibraryPath))),
+            // frameworks are looked up with their partial path
+            ("@executable_path/../Frameworks/Python.framework/Versions/3.9/Python", &no_env, Some((p("env/bin/../Frameworks/Python.framework/Versions/3.9/Python"), SearchStep::ExecutablePath))),
+            ("@executable_path/../Frameworks/Python.framework/Versions/3.9/Python", &with_env, Some((p("dyld_frameworks/Python.framework/Versions/3.9/Python"), SearchStep::DyldFrameworkPath))),
+            ("/Library/Frameworks/Tk.framework/Versions/8.6/Tk", &no_env, Some((p("fallback_frameworks/Tk.framework/Versions/8.6/Tk"), SearchStep::DyldFallbackFrameworkPath))),
+            // a framework is not found through the library paths
+            ("/Library/Frameworks/Foo.framework/Versions/A/Foo", &with_env, None),
+            ("@rpath/libmissing.dylib", &with_env, None),
+        ];
+        for (install_name, dyld, expected) in cases {
+            let mut tried = Vec::new();
+            let found = search(
+                install_name,
+                &rpaths,
+                &inherited_rpaths,
+                &loader_path,
+                &executable_path,
+                &cwd,
+                dyld,
+                &mut tried,
+            );
+            assert_eq!(found, expected, "install_name={}", install_name);
+            if let Some((winner, _)) = found {
+                assert_eq!(tried.last(), Some(&winner), "install_name={}", install_name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_framework_partial_path() {
+        let cases = [
+            ("/Library/Frameworks/Python.framework/Versions/3.9/Python", Some("Python.framework/Versions/3.9/Python")),
+            ("@rpath/Qt.framework/Qt_debug", Some("Qt.framework/Qt_debug")),
+            ("/opt/x.framework/libfoo.dylib", None),
+            ("@rpath/libfoo.dylib", None),
+        ];
+        for (install_name, expected) in cases {
+            assert_eq!(framework_partial_path(install_name), expected, "install_name={}", install_name);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// the graph as it would be exported, for inspecting why a file was pulled in
+// every node has its `Pkg` variant and paths in dist, every edge has the string the dependency was asked for with
+
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{factory::Factory, graph::FileGraph, node::Node, pkg::paths::ExportedFileTree};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphView {
+    pub nodes: Vec<NodeView>,
+
+    pub edges: Vec<EdgeView>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeView {
+    pub path: PathBuf,
+
+    // the `Pkg` variant of the node
+    pub kind: String,
+
+    pub sha: Option<String>,
+
+    pub reals: Option<PathBuf>,
+
+    pub destination: Option<PathBuf>,
+}
+
+/// `from` needs `to`
+#[derive(Debug, Clone, Serialize)]
+pub struct EdgeView {
+    pub from: PathBuf,
+
+    pub to: PathBuf,
+
+    // DT_NEEDED or load command strings which resolved to `to`, empty for dependencies we don't know the string of
+    pub needed: Vec<NeededView>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NeededView {
+    pub name: String,
+
+    // the search step which found `to`, and the candidates checked on the way
+    pub step: Option<String>,
+
+    pub tried: Vec<PathBuf>,
+}
+
+impl NeededView {
+    fn label(&self) -> String {
+        match &self.step {
+            Some(step) => format!("{} ({})", self.name, step),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// view of the whole graph, or only of `from` and its transitive dependencies
+pub fn view_graph<T: Factory>(graph: &FileGraph<T>, dist: &PathBuf, from: Option<&PathBuf>) -> Result<GraphView> {
+    let mut nodes: Vec<Node> = match from {
+        Some(path) => graph.reachable_from(path)?,
+        None => graph.iter_nodes().cloned().collect(),
+    };
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut edges = Vec::new();
+    for node in &nodes {
+        let needed = node.deps.needed();
+        let mut deps = graph.get_node_dependencies(node);
+        deps.sort_by(|a, b| a.path.cmp(&b.path));
+        for dep in deps {
+            edges.push(EdgeView {
+                from: node.path.clone(),
+                to: dep.path.clone(),
+                needed: needed
+                    .iter()
+                    .filter(|(_, p)| *p == dep.path)
+                    .map(|(name, _)| {
+                        let resolution = node.deps.resolution(name);
+                        NeededView {
+                            name: name.clone(),
+                            step: resolution.map(|r| r.step.to_string()),
+                            tried: resolution.map(|r| r.tried.clone()).unwrap_or_default(),
+                        }
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    let nodes = nodes
+        .iter()
+        .map(|node| NodeView {
+            path: node.path.clone(),
+            kind: node.pkg.kind().to_string(),
+            sha: node.pkg.sha().map(|s| s.to_string()),
+            reals: node.pkg.reals(node, dist),
+            destination: node.pkg.destination(&node.path, dist),
+        })
+        .collect();
+    Ok(GraphView { nodes, edges })
+}
+
+pub fn write_graph_json(view: &GraphView, out: &mut impl Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, view).context("failed in serializing graph")?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// graphviz DOT, nodes are identified by their path and labelled with the file name and `Pkg` variant
+pub fn write_graph_dot(view: &GraphView, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "digraph yarp {{")?;
+    writeln!(out, "  rankdir=LR;")?;
+    writeln!(out, "  node [shape=box];")?;
+    for node in &view.nodes {
+        let name = node
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| node.path.display().to_string());
+        let mut label = format!("{}\n{}", name, node.kind);
+        if let Some(sha) = &node.sha {
+            label.push_str(&format!("\nsha={}", &sha[..sha.len().min(12)]));
+        }
+        if let Some(dest) = node.destination.as_ref().or(node.reals.as_ref()) {
+            label.push_str(&format!("\n{}", dest.display()));
+        }
+        writeln!(
+            out,
+            "  {} [label={}, tooltip={}];",
+            quote(&node.path.display().to_string()),
+            quote(&label),
+            quote(&node.path.display().to_string())
+        )?;
+    }
+    for edge in &view.edges {
+        writeln!(
+            out,
+            "  {} -> {} [label={}];",
+            quote(&edge.from.display().to_string()),
+            quote(&edge.to.display().to_string()),
+            quote(
+                &edge
+                    .needed
+                    .iter()
+                    .map(|n| n.label())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        )?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{EdgeView, GraphView, NeededView, NodeView, write_graph_dot};
+
+    #[test]
+    fn test_write_graph_dot() {
+        let view = GraphView {
+            nodes: vec![NodeView {
+                path: PathBuf::from("/env/lib/libz.so.1"),
+                kind: "BinaryInLDPath".to_string(),
+                sha: Some("0123456789abcdef".to_string()),
+                reals: Some(PathBuf::from("/dist/reals/r/libz.so.1")),
+                destination: Some(PathBuf::from("/dist/lib/l/libz.so.1")),
+            }],
+            edges: vec![EdgeView {
+                from: PathBuf::from("/env/site-packages/a\"b.so"),
+                to: PathBuf::from("/env/lib/libz.so.1"),
+                needed: vec![NeededView {
+                    name: "libz.so.1".to_string(),
+                    step: Some("DT_RUNPATH".to_string()),
+                    tried: vec![PathBuf::from("/env/lib/libz.so.1")],
+                }],
+            }],
+        };
+        let mut out = Vec::new();
+        write_graph_dot(&view, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.contains(
+            "\"/env/lib/libz.so.1\" [label=\"libz.so.1\\nBinaryInLDPath\\nsha=0123456789ab\\n/dist/lib/l/libz.so.1\""
+        ));
+        assert!(dot.contains("\"/env/site-packages/a\\\"b.so\" -> \"/env/lib/libz.so.1\" [label=\"libz.so.1 (DT_RUNPATH)\"];"));
+    }
+}
@@ -1,70 +1,55 @@
-use std::{
-    path::PathBuf,
-    process::{Command, Stdio},
-};
+use std::path::PathBuf;
 
 use anyhow::{Result, anyhow, bail};
 use pathdiff::diff_paths;
 
-use crate::{parse::Elf, paths::get_lib_name};
+use crate::{
+    parse::Elf,
+    paths::get_lib_name,
+    pkg::patch::op::PatchOp,
+};
 
 
-pub fn patch_elf_for_destination(dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+pub fn plan_elf_for_destination(dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>> {
     let rpath = get_new_rpath(dest_path, symlink_farm_path)?;
-    add_rpath(&rpath, real_path)?;
-    Ok(())
+    Ok(vec![add_rpath(&rpath, real_path)?])
 }
 
-pub fn patch_elf(elf: &Elf, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+pub fn plan_elf(elf: &Elf, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>> {
     // TODO: add support to download patchelf if it does not exist
     // TODO: linux does not need a symlink farm, the reals path would simply be the libname
     // and we just add everything in the same folder, the final rpath would also simply be $ORIGIN
     // for now doing the mac structure
     // why this matters is that the rpath can be bigger than what is there originally in the binary
     // this might result in us not being able to patch it
-    rm_rpath(reals_path)?;
-    add_rpath(&get_new_rpath(reals_path, symlink_farm_path)?, reals_path)?;
-    modify_all_dt_needed(reals_path, symlink_farm_path, elf)?;
-    Ok(())
+    let mut ops = vec![
+        rm_rpath(reals_path)?,
+        add_rpath(&get_new_rpath(reals_path, symlink_farm_path)?, reals_path)?,
+    ];
+    if let Some(soname) = &elf.new_soname {
+        ops.push(set_soname(soname, reals_path)?);
+    }
+    for (old, parent_path) in &elf.dt_needed {
+        let lib_name = needed_lib_name(elf, old, parent_path)?;
+        ops.push(modify_dt_needed(&old, &lib_name, reals_path)?);
+    }
+    Ok(ops)
 }
 
-fn rm_rpath(path: &PathBuf) -> Result<()> {
-    let output = Command::new("patchelf")
-        .stderr(Stdio::null())
-        .arg("--remove-rpath")
-        .arg(path)
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running patchelf to delete rpath path={} status={:?} stdout={} stderr={}",
-            path.display(),
-            output.status,
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr),
-        )
+// the name of the dependency inside the symlink farm, a renamed dependency is linked with its new SONAME
+fn needed_lib_name(elf: &Elf, needed: &str, parent_path: &PathBuf) -> Result<String> {
+    match elf.renamed_needed.get(needed) {
+        Some(name) => Ok(name.clone()),
+        None => get_lib_name(parent_path),
     }
 }
 
-fn add_rpath(rpath: &str, path: &PathBuf) -> Result<()> {
-    let status = Command::new("patchelf")
-        .stderr(Stdio::null())
-        .arg("--add-rpath")
-        .arg(rpath)
-        .arg(path)
-        .status()?;
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running patchelf to set rpath path={} rpath={} status={:?}",
-            path.display(),
-            rpath,
-            status
-        )
-    }
+fn rm_rpath(path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("patchelf", &["--remove-rpath"], path)
+}
+
+fn add_rpath(rpath: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("patchelf", &["--add-rpath", rpath], path)
 }
 
 // TODO: remove this duplication from mac
@@ -91,9 +76,9 @@ fn get_new_rpath(real_path: &PathBuf, symlink_farm: &PathBuf) -> Result<String>
     Ok(format!("$ORIGIN/{}/", rel_path))
 }
 
-fn modify_all_dt_needed(reals_path: &PathBuf, symlink_farm_path: &PathBuf, elf: &Elf) -> Result<()> {
-    for (old, parent_path) in &elf.dt_needed {
-        let lib_name = get_lib_name(&parent_path)?;
+pub fn check_all_dt_needed_in_farm(reals_path: &PathBuf, symlink_farm_path: &PathBuf, elf: &Elf) -> Result<()> {
+    for (needed, parent_path) in &elf.dt_needed {
+        let lib_name = needed_lib_name(elf, needed, parent_path)?;
         let lib_in_farm = symlink_farm_path.join(&lib_name);
         if !lib_in_farm.exists() {
             bail!(
@@ -103,52 +88,17 @@ fn modify_all_dt_needed(reals_path: &PathBuf, symlink_farm_path: &PathBuf, elf:
                 lib_name
             );
         }
-        modify_dt_needed(&old, &lib_name, reals_path)?;
     }
     Ok(())
 }
 
 
 
-fn modify_dt_needed(old: &str, new: &str, path: &PathBuf) -> Result<()> {
-    let status = Command::new("patchelf")
-        .stderr(Stdio::null())
-        .arg("--replace-needed")
-        .arg(old)
-        .arg(new)
-        .arg(path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running patchelf to modifying DT_NEEDED path={} old={} new={} status={:?}",
-            old,
-            new,
-            path.display(),
-            status
-        )
-    }
+fn modify_dt_needed(old: &str, new: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("patchelf", &["--replace-needed", old, new], path)
 }
 
 
-// fn set_so_name(name: &str, path: &PathBuf) -> Result<()> {
-//     let status = Command::new("patchelf")
-//         .stderr(Stdio::null())
-//         .arg("--set-soname")
-//         .arg(name)
-//         .arg(path)
-//         .status()?;
-
-//     if status.success() {
-//         Ok(())
-//     } else {
-//         bail!(
-//             "failed in running patchelf to set SONAME path={} name={} status={:?}",
-//             path.display(),
-//             name,
-//             status
-//         )
-//     }
-// }
\ No newline at end of file
+fn set_soname(name: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("patchelf", &["--set-soname", name], path)
+}
@@ -1,18 +1,19 @@
-use crate::{parse::Macho, paths::get_lib_name};
+use crate::{
+    parse::{DylibKind, Macho},
+    paths::get_lib_name,
+    pkg::patch::op::PatchOp,
+};
 // patching libraries to work with the new symlink tree
 // basically all install_name_tool operations
 
-use std::{
-    path::PathBuf,
-    process::{Command, Stdio},
-};
+use std::path::PathBuf;
 
 use anyhow::{Result, anyhow, bail};
 use pathdiff::diff_paths;
 
-pub fn patch_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+pub fn plan_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>> {
     if mach.load_cmds.len() == 0 {
-        return Ok(());
+        return Ok(Vec::new());
     }
     // the order of these operations are important
     // for many dylibs, if you simply modify a load command, and if the size of the new load command is bigger than the older one
@@ -25,7 +26,6 @@ pub fn patch_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathB
     // because we simply use @rpath/libname, this is smaller than almost every other prefix based path system
     // only libname as a relative path is generally smaller
     // it is working well in practice
-
     // TODO: create a fallback which would create symlink farms in every place the original dylib expects data to be in
     // if this whole procedure fails (we can't fit at all), we have to simply make a structure that works with existing dylib
     // the simple solution is to go through all load commands, find the directory where dyld will look for each load command, and make a symlink farm there
@@ -35,24 +35,30 @@ pub fn patch_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathB
     // its almost impossible to make relative paths work though (our directory of calling the binary is not fixed, although the bootstrap script can do this for us)
     // the first hope is to replace with rpaths, and then see what can be done
     // in any case, the operation is basically a map on load commands at a top level (which changes them to smaller variants) given an rpath
+    let mut ops = Vec::new();
     for rpath in &mach.all_rpaths {
-        rm_rpath(rpath, reals_path)?;
+        ops.push(rm_rpath(rpath, reals_path)?);
     }
     let lib_name = get_lib_name(reals_path)?;
     let rpath = get_new_rpath(reals_path, symlink_farm_path)?;
-    modify_load_cmds(reals_path, symlink_farm_path, mach)?;
-    add_rpath(&rpath, reals_path)?;
-    set_dylib_id(dylib_id(&lib_name), &reals_path)?;
-    sign_dylib(&reals_path)?;
+    ops.extend(modify_load_cmds(reals_path, mach)?);
+    ops.push(add_rpath(&rpath, reals_path)?);
+    ops.push(set_dylib_id(dylib_id(&lib_name), &reals_path)?);
+    ops.push(sign_dylib(&reals_path)?);
 
-    Ok(())
+    Ok(ops)
 }
 
-fn modify_load_cmds(reals_path: &PathBuf, symlink_farm_path: &PathBuf, mach: &Macho) -> Result<()> {
+pub fn check_all_load_cmds_in_farm(reals_path: &PathBuf, symlink_farm_path: &PathBuf, mach: &Macho) -> Result<()> {
     for (load_cmd, parent_path) in &mach.load_cmds {
         let lib_name = get_lib_name(&parent_path)?;
         let lib_in_farm = symlink_farm_path.join(&lib_name);
-        if !lib_in_farm.exists() {
+        let in_farm = match mach.kinds.get(load_cmd) {
+            // upward dylibs can be exported after us, the link dangles till their reals are made
+            Some(DylibKind::Upward) => lib_in_farm.symlink_metadata().is_ok(),
+            _ => lib_in_farm.exists(),
+        };
+        if !in_farm {
             bail!(
                 "fatal: did not find dependency in symlink farm, path={} symlink-farm={} lib_name={}",
                 reals_path.display(),
@@ -60,12 +66,25 @@ fn modify_load_cmds(reals_path: &PathBuf, symlink_farm_path: &PathBuf, mach: &Ma
                 lib_name
             );
         }
-        modify_load_command(&load_cmd, &dylib_id(&lib_name), reals_path)?;
-        sign_dylib(reals_path)?;
     }
     Ok(())
 }
 
+// `-change` rewrites the path of a dylib load command of any kind, a weak, re-exported, lazy or upward dependency stays one
+// re-exported dylibs are bundled like any other dependency, dyld finds them through our rpath when our dependents load us
+// unresolved weak load commands are not in the farm and are left as they are, dyld skips them if they don't exist
+fn modify_load_cmds(reals_path: &PathBuf, mach: &Macho) -> Result<Vec<PatchOp>> {
+    let mut ops = Vec::new();
+    let mut load_cmds: Vec<(&String, &PathBuf)> = mach.load_cmds.iter().collect();
+    load_cmds.sort();
+    for (load_cmd, parent_path) in load_cmds {
+        let lib_name = get_lib_name(&parent_path)?;
+        ops.push(modify_load_command(&load_cmd, &dylib_id(&lib_name), reals_path)?);
+        ops.push(sign_dylib(reals_path)?);
+    }
+    Ok(ops)
+}
+
 fn dylib_id(lib_name: &str) -> String {
     format!("@rpath/{}", lib_name)
 }
@@ -94,104 +113,102 @@ fn get_new_rpath(real_path: &PathBuf, symlink_farm: &PathBuf) -> Result<String>
     Ok(format!("@loader_path/{}/", rel_path))
 }
 
-fn rm_rpath(rpath: &str, path: &PathBuf) -> Result<()> {
-    let status = Command::new("install_name_tool")
-        .stderr(Stdio::null())
-        .arg("-delete_rpath")
-        .arg(rpath)
-        .arg(path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running install_name_tool to delete rpath path={} rpath={} status={:?}",
-            rpath,
-            path.display(),
-            status
-        )
-    }
+fn rm_rpath(rpath: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("install_name_tool", &["-delete_rpath", rpath], path)
 }
 
-fn add_rpath(rpath: &str, path: &PathBuf) -> Result<()> {
-    let status = Command::new("install_name_tool")
-        .stderr(Stdio::null())
-        .arg("-add_rpath")
-        .arg(rpath)
-        .arg(path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running install_name_tool to add rpath path={} rpath={} status={:?}",
-            path.display(),
-            rpath,
-            status
-        )
-    }
+fn add_rpath(rpath: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("install_name_tool", &["-add_rpath", rpath], path)
 }
 
-fn modify_load_command(old: &str, new: &str, path: &PathBuf) -> Result<()> {
-    let status = Command::new("install_name_tool")
-        .stderr(Stdio::null())
-        .arg("-change")
-        .arg(old)
-        .arg(new)
-        .arg(path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running install_name_tool to modifying load command path={} old={} new={} status={:?}",
-            old,
-            new,
-            path.display(),
-            status
-        )
-    }
+fn modify_load_command(old: &str, new: &str, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("install_name_tool", &["-change", old, new], path)
 }
 
-fn set_dylib_id(id: String, path: &PathBuf) -> Result<()> {
-    let status = Command::new("install_name_tool")
-        .stderr(Stdio::null())
-        .arg("-id")
-        .arg(&id)
-        .arg(path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running install_name_tool to set dylib_id path={} id={} status={:?}",
-            id,
-            path.display(),
-            status
-        )
-    }
+fn set_dylib_id(id: String, path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("install_name_tool", &["-id", &id], path)
+}
+
+fn sign_dylib(path: &PathBuf) -> Result<PatchOp> {
+    PatchOp::on_file("codesign", &["-s", "-", "-f"], path)
 }
 
-fn sign_dylib(path: &PathBuf) -> Result<()> {
-    let status = Command::new("codesign")
-        .stderr(Stdio::null())
-        .arg("-s")
-        .arg("-")
-        .arg("-f")
-        .arg(&path)
-        .status()?;
-
-    if status.success() {
-        Ok(())
-    } else {
-        bail!(
-            "failed in running signing dylib_id path={} status={:?}",
-            path.display(),
-            status
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use crate::parse::{DylibKind, Macho};
+
+    use super::{check_all_load_cmds_in_farm, plan_macho};
+
+    #[test]
+    fn test_plan_load_command_kinds() {
+        let dep = |name: &str| PathBuf::from("/env/lib").join(name);
+        let load_cmds = HashMap::from([
+            ("@rpath/libb.dylib".to_string(), dep("libb.dylib")),
+            ("/env/lib/libc.dylib".to_string(), dep("libc.dylib")),
+            ("@loader_path/libd.dylib".to_string(), dep("libd.dylib")),
+        ]);
+        let kinds = HashMap::from([
+            ("@rpath/libb.dylib".to_string(), DylibKind::Weak),
+            ("/env/lib/libc.dylib".to_string(), DylibKind::Reexport),
+            ("@loader_path/libd.dylib".to_string(), DylibKind::Upward),
+        ]);
+        let mach = Macho {
+            load_cmds,
+            resolutions: HashMap::new(),
+            rpaths: HashMap::new(),
+            id_dylib: Some("@rpath/liba.dylib".to_string()),
+            path: dep("liba.dylib"),
+            all_rpaths: vec!["@loader_path".to_string()],
+            kinds,
+            missing_weak: vec!["@rpath/libmissing.dylib".to_string()],
+        };
+        let ops = plan_macho(
+            &mach,
+            &PathBuf::from("/dist/reals/r/aaaa.dylib"),
+            &PathBuf::from("/dist/symlinks/aaaa"),
         )
+        .unwrap();
+        let changes: Vec<Vec<String>> = ops
+            .iter()
+            .filter(|op| op.args.first().map(|a| a.as_str()) == Some("-change"))
+            .map(|op| op.args[1..3].to_vec())
+            .collect();
+        // every resolved kind is pointed at the farm, the missing weak dylib is left alone
+        assert_eq!(
+            changes,
+            vec![
+                vec!["/env/lib/libc.dylib".to_string(), "@rpath/libc.dylib".to_string()],
+                vec!["@loader_path/libd.dylib".to_string(), "@rpath/libd.dylib".to_string()],
+                vec!["@rpath/libb.dylib".to_string(), "@rpath/libb.dylib".to_string()],
+            ]
+        );
+        assert!(!ops.iter().any(|op| op.args.iter().any(|a| a.contains("libmissing"))));
+    }
+
+    #[test]
+    fn test_farm_check_allows_dangling_upward() {
+        let tmp = tempfile::tempdir().unwrap();
+        let farm = tmp.path().join("symlinks/aaaa");
+        std::fs::create_dir_all(&farm).unwrap();
+        // libe's reals are made after us, the link points nowhere yet
+        std::os::unix::fs::symlink("../../reals/r/bbbb.dylib", farm.join("libe.dylib")).unwrap();
+        let load_cmd = "@rpath/libe.dylib".to_string();
+        let mut mach = Macho {
+            load_cmds: HashMap::from([(load_cmd.clone(), PathBuf::from("/env/lib/libe.dylib"))]),
+            resolutions: HashMap::new(),
+            rpaths: HashMap::new(),
+            id_dylib: Some("@rpath/libkinds.dylib".to_string()),
+            path: PathBuf::from("/env/lib/libkinds.dylib"),
+            all_rpaths: Vec::new(),
+            kinds: HashMap::from([(load_cmd.clone(), DylibKind::Upward)]),
+            missing_weak: Vec::new(),
+        };
+        let reals = tmp.path().join("reals/r/aaaa.dylib");
+        check_all_load_cmds_in_farm(&reals, &farm, &mach).unwrap();
+
+        mach.kinds.insert(load_cmd, DylibKind::Load);
+        assert!(check_all_load_cmds_in_farm(&reals, &farm, &mach).is_err());
     }
 }
@@ -1,75 +1,56 @@
 // patching libraries to work with the new symlink tree
 // basically all install_name_tool operations
 
-use std::{
-    path::PathBuf,
-    process::{Command, Stdio},
-};
+use std::path::PathBuf;
+
+use anyhow::Result;
 
-use anyhow::{Result, anyhow, bail};
-use pathdiff::diff_paths;
+use crate::pkg::patch::{
+    elf::{check_all_dt_needed_in_farm, plan_elf, plan_elf_for_destination},
+    macho::{check_all_load_cmds_in_farm, plan_macho},
+};
+use crate::{node::deps::Deps, parse::Binary};
 
-use crate::{parse::Macho, pkg::patch::elf::{patch_elf, patch_elf_for_destination}};
-use crate::{node::deps::Deps, parse::Binary, pkg::patch::macho::patch_macho};
+pub use op::{PatchOp, run_all};
 
 mod macho;
 mod elf;
+mod op;
 
 pub trait LibPatch {
-    fn patch(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()>;
+    // fails if a dependency the patched binary would ask for is missing in its symlink farm
+    fn check_farm(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()>;
 
-    fn patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()>;
+    // the operations patching the reals to load dependencies from the symlink farm, nothing is executed
+    fn plan_patch(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>>;
+
+    // the operations for a binary which is also linked at a destination (like site-packages), nothing is executed
+    fn plan_patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>>;
 }
 
 impl LibPatch for Deps {
-    fn patch(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+    fn check_farm(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
         match self {
-            Deps::Plain => Ok(()),
-            Deps::Binary(binary) => {
-                patch_lib(real_path, &binary, symlink_farm_path)?;
-                Ok(())
-            }
-            #[cfg(test)]
-            Deps::Mock { paths: _ } => Ok(()),
+            Deps::Binary(Binary::Macho(mach)) => check_all_load_cmds_in_farm(real_path, symlink_farm_path, mach),
+            Deps::Binary(Binary::Elf(elf)) => check_all_dt_needed_in_farm(real_path, symlink_farm_path, elf),
+            _ => Ok(()),
         }
     }
 
-
-    fn patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+    fn plan_patch(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>> {
         match self {
-            Deps::Plain => Ok(()),
-            Deps::Binary(binary) => {
-                match binary {
-                    Binary::Elf(_) =>  {
-                        patch_elf_for_destination(dest_path, real_path, symlink_farm_path)?;
-                    },
-                    _=> {}
-                };
-                Ok(())
-            }
+            Deps::Plain => Ok(Vec::new()),
+            Deps::Binary(Binary::Macho(mach)) => plan_macho(mach, real_path, symlink_farm_path),
+            Deps::Binary(Binary::Elf(elf)) => plan_elf(elf, real_path, symlink_farm_path),
             #[cfg(test)]
-            Deps::Mock { paths: _ } => Ok(()),
+            Deps::Mock { paths: _ } => Ok(Vec::new()),
         }
     }
-}
-
-pub fn patch_lib(reals_path: &PathBuf, binary: &Binary, symlink_farm_path: &PathBuf) -> Result<()> {
-    // deps is a vector of shared library names, generated from the graph
-    // im assuming that symlink farm location is hardcoded here
-    // TODO: make this less hardcoded, we should simply find the relative path of symlink farm from reals
-    // rpaths etc should use that string instead of hardcoding everything
 
-    match binary {
-        Binary::Macho(mach) => {
-            patch_macho(mach, reals_path, symlink_farm_path)?;
-        }
-        Binary::Elf(elf) => {
-            // nothing for elfs
-            // we put the reals path in the ld_library_path
-            // TODO: make symlinks from reals to actual library path instead of this
-            // this is a hack
-            patch_elf(elf, reals_path, symlink_farm_path)?;
+    fn plan_patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<Vec<PatchOp>> {
+        match self {
+            Deps::Binary(Binary::Elf(_)) => plan_elf_for_destination(dest_path, real_path, symlink_farm_path),
+            _ => Ok(Vec::new()),
         }
-    };
-    Ok(())
+    }
 }
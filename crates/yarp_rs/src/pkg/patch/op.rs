@@ -0,0 +1,111 @@
+// a single invocation of an external patching tool (patchelf, install_name_tool, codesign)
+// patching first plans all operations and then runs them, this lets us print the plan without touching anything
+
+use std::{
+    fmt::Display,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Error, Result, bail};
+use serde::Serialize;
+
+use crate::{error::YarpError, paths::to_string_path};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatchOp {
+    pub tool: String,
+    pub args: Vec<String>,
+}
+
+impl PatchOp {
+    pub fn new(tool: &str, args: Vec<String>) -> PatchOp {
+        PatchOp {
+            tool: tool.to_string(),
+            args,
+        }
+    }
+
+    /// the same as `new`, the last argument is the file being patched
+    pub fn on_file(tool: &str, args: &[&str], path: &Path) -> Result<PatchOp> {
+        let mut all_args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        all_args.push(to_string_path(path)?);
+        Ok(PatchOp::new(tool, all_args))
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let output = match Command::new(&self.tool)
+            .stdin(Stdio::null())
+            .args(&self.args)
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(Error::new(YarpError::ToolMissing {
+                    tool: self.tool.clone(),
+                }));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            Ok(())
+        } else if self.tool == "install_name_tool" && stderr.contains("do not fit") {
+            // install_name_tool: "larger updated load commands do not fit (the program must be relinked...)"
+            Err(Error::new(YarpError::HeaderPadExhausted {
+                path: self.args.last().map(PathBuf::from).unwrap_or_default(),
+            }))
+        } else {
+            bail!(
+                "failed in running `{}` status={:?} stdout={} stderr={}",
+                self,
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                stderr,
+            )
+        }
+    }
+}
+
+impl Display for PatchOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tool)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run_all(ops: &Vec<PatchOp>) -> Result<()> {
+    for op in ops {
+        op.run()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::PatchOp;
+
+    #[test]
+    fn test_on_file_appends_path() {
+        let op = PatchOp::on_file(
+            "patchelf",
+            &["--replace-needed", "libfoo.so.1", "libfoo.so"],
+            &PathBuf::from("/dist/reals/r/abc.so"),
+        )
+        .unwrap();
+        assert_eq!(
+            op.args,
+            vec!["--replace-needed", "libfoo.so.1", "libfoo.so", "/dist/reals/r/abc.so"]
+        );
+        assert_eq!(
+            op.to_string(),
+            "patchelf --replace-needed libfoo.so.1 libfoo.so /dist/reals/r/abc.so"
+        );
+    }
+}
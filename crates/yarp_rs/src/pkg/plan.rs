@@ -0,0 +1,202 @@
+// the export plan: the reals, symlink farm links and patches of every node
+// `move_to_dist` runs the plan, `export --plan` only prints it
+
+use std::{ffi::OsStr, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+
+use crate::{
+    node::Node,
+    pkg::{LibPatch, patch::PatchOp, paths::ExportedFileTree},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodePlan {
+    pub path: PathBuf,
+
+    // the `Pkg` variant of the node
+    pub kind: String,
+
+    pub reals: Option<PathBuf>,
+
+    pub symlink_farm: Option<PathBuf>,
+
+    pub destination: Option<PathBuf>,
+
+    // links made inside the symlink farm
+    pub links: Vec<FarmLink>,
+
+    // external tool invocations, in the order they would be run
+    pub patches: Vec<PatchOp>,
+}
+
+/// a dependency linked in a symlink farm
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FarmLink {
+    // the file name of the dependency, or its new SONAME if it was renamed to avoid a collision
+    pub name: String,
+
+    pub dep: PathBuf,
+
+    // the reals of the dependency, the link points here
+    pub reals: PathBuf,
+}
+
+pub fn plan_node(node: &Node, deps: &Vec<Node>, dist: &PathBuf) -> Result<NodePlan> {
+    let reals = node.pkg.reals(node, dist);
+    let symlink_farm = node.pkg.symlink_farm(&node.path, dist);
+    let destination = node.pkg.destination(&node.path, dist);
+
+    let mut patches = Vec::new();
+    if let (Some(real_path), Some(farm)) = (reals.as_ref(), symlink_farm.as_ref()) {
+        patches.extend(node.deps.plan_patch(real_path, farm).with_context(|| {
+            anyhow!("failed in planning patch for node, path={}", node.path.display())
+        })?);
+        if let Some(dest) = destination.as_ref() {
+            patches.extend(
+                node.deps
+                    .plan_patch_for_destination(dest, real_path, farm)
+                    .with_context(|| {
+                        anyhow!(
+                            "failed in planning patch for destination, path={}",
+                            node.path.display()
+                        )
+                    })?,
+            );
+        }
+    }
+
+    let links = match symlink_farm {
+        Some(_) => farm_links(deps, dist)?,
+        None => Vec::new(),
+    };
+
+    Ok(NodePlan {
+        path: node.path.clone(),
+        kind: node.pkg.kind().to_string(),
+        reals,
+        symlink_farm,
+        destination,
+        links,
+        patches,
+    })
+}
+
+// only dependencies with reals are linked
+fn farm_links(deps: &Vec<Node>, dist: &PathBuf) -> Result<Vec<FarmLink>> {
+    let mut links = Vec::new();
+    for dep in deps {
+        let reals = match dep.pkg.reals(dep, dist) {
+            Some(reals) => reals,
+            None => continue,
+        };
+        // a dependency renamed to avoid a SONAME collision is linked with its new name
+        let name = match dep.deps.new_soname() {
+            Some(name) => OsStr::new(name),
+            None => dep.path.file_name().ok_or_else(|| {
+                anyhow!("could not find file_name for creating symlink for dependency, path={}", reals.display())
+            })?,
+        };
+        links.push(FarmLink {
+            name: name.to_string_lossy().to_string(),
+            dep: dep.path.clone(),
+            reals,
+        });
+    }
+    Ok(links)
+}
+
+pub fn write_plan_text(plans: &Vec<NodePlan>, out: &mut impl Write) -> Result<()> {
+    for plan in plans {
+        writeln!(out, "{} [{}]", plan.path.display(), plan.kind)?;
+        write_optional_path(out, "reals", &plan.reals)?;
+        write_optional_path(out, "symlink farm", &plan.symlink_farm)?;
+        write_optional_path(out, "destination", &plan.destination)?;
+        for link in &plan.links {
+            writeln!(out, "    link: {} -> {}", link.name, link.dep.display())?;
+        }
+        for op in &plan.patches {
+            writeln!(out, "    patch: {}", op)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_plan_json(plans: &Vec<NodePlan>, out: &mut impl Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, plans).context("failed in serializing export plan")?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_optional_path(out: &mut impl Write, name: &str, path: &Option<PathBuf>) -> Result<()> {
+    if let Some(path) = path {
+        writeln!(out, "    {}: {}", name, path.display())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use crate::{
+        node::{Node, Pkg, deps::Deps},
+        parse::{Binary, Elf},
+    };
+
+    use super::{FarmLink, plan_node};
+
+    fn elf(path: &PathBuf, dt_needed: &[(&str, &PathBuf)]) -> Deps {
+        Deps::Binary(Binary::Elf(Elf {
+            soname: path.file_name().unwrap().to_string_lossy().to_string(),
+            dt_needed: dt_needed
+                .iter()
+                .map(|(name, p)| (name.to_string(), (*p).clone()))
+                .collect(),
+            resolutions: HashMap::new(),
+            dt_rpaths: HashMap::new(),
+            dt_runpaths: HashMap::new(),
+            path: path.clone(),
+            all_dt_rpaths: Vec::new(),
+            all_dt_runpaths: Vec::new(),
+            new_soname: None,
+            renamed_needed: HashMap::new(),
+        }))
+    }
+
+    #[test]
+    fn test_plan_node() {
+        let tmp = tempfile::tempdir().unwrap();
+        let gomp = tmp.path().join("libgomp.so.1");
+        let ext = tmp.path().join("_ext.so");
+        std::fs::File::create(&gomp).unwrap();
+        std::fs::File::create(&ext).unwrap();
+
+        // libgomp collided with another libgomp.so.1, it is exported with a hashed SONAME
+        let mut gomp_deps = elf(&gomp, &[]);
+        gomp_deps.set_new_soname("libgomp-aaaaaaaa.so.1");
+        let gomp_node = Node::new(gomp.clone(), Pkg::Binary { sha: "aaaa".to_string() }, gomp_deps).unwrap();
+        let mut ext_deps = elf(&ext, &[("libgomp.so.1", &gomp)]);
+        ext_deps.rename_needed(&gomp, "libgomp-aaaaaaaa.so.1");
+        let ext_node = Node::new(ext.clone(), Pkg::Binary { sha: "bbbb".to_string() }, ext_deps).unwrap();
+
+        let plan = plan_node(&ext_node, &vec![gomp_node], &PathBuf::from("/dist")).unwrap();
+        assert_eq!(plan.reals, Some(PathBuf::from("/dist/reals/r/bbbb.so")));
+        assert_eq!(
+            plan.links,
+            vec![FarmLink {
+                name: "libgomp-aaaaaaaa.so.1".to_string(),
+                dep: gomp,
+                reals: PathBuf::from("/dist/reals/r/aaaa.1"),
+            }]
+        );
+        // the farm link and the patched DT_NEEDED agree on the name
+        let replace = plan
+            .patches
+            .iter()
+            .find(|op| op.args[0] == "--replace-needed")
+            .unwrap();
+        assert_eq!(replace.args[1..3], ["libgomp.so.1", "libgomp-aaaaaaaa.so.1"]);
+    }
+}
@@ -0,0 +1,265 @@
+// crash-safe dist creation
+// the export is written to a sibling staging directory, which is renamed to the final dist only after everything succeeded
+// everything inside dist is relative (symlinks, rpaths, bootstrap script), so renaming the directory is safe
+// if we fail (or are interrupted) midway, the staging directory is removed and the older dist is left untouched
+// a killed process can't clean up, its staging directory is removed by the next export once the process is gone
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+
+#[derive(Debug)]
+pub struct StagingDist {
+    // the directory nodes are exported to
+    staging: PathBuf,
+
+    // the final location of dist
+    dist: PathBuf,
+
+    // set once staging has been moved to dist, there is nothing to clean up after that
+    committed: bool,
+}
+
+impl StagingDist {
+    pub fn new(dist: &PathBuf) -> Result<StagingDist> {
+        let staging = sibling_path(dist, "staging")?;
+        for stale in stale_staging_dirs(dist)? {
+            // left behind by a process that was killed without getting a chance to clean up
+            warn!("found stale staging directory, removing. path={}", stale.display());
+            fs::remove_dir_all(&stale).with_context(|| {
+                anyhow!(
+                    "failed in removing stale staging directory, path={}",
+                    stale.display()
+                )
+            })?;
+        }
+        fs::create_dir_all(&staging).with_context(|| {
+            anyhow!("failed in creating staging directory, path={}", staging.display())
+        })?;
+        install_interrupt_cleanup(&staging);
+        info!("exporting to staging directory, path={}", staging.display());
+        Ok(StagingDist {
+            staging,
+            dist: dist.clone(),
+            committed: false,
+        })
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.staging
+    }
+
+    /// swap the staging directory into the final dist location
+    /// an existing dist is only replaced if `force` is set
+    pub fn commit(mut self, force: bool) -> Result<()> {
+        let old = if self.dist.exists() {
+            if !force {
+                bail!(
+                    "output directory already exists, pass --force to overwrite it, path={}",
+                    self.dist.display()
+                );
+            }
+            let old = sibling_path(&self.dist, "old")?;
+            if old.exists() {
+                fs::remove_dir_all(&old)?;
+            }
+            fs::rename(&self.dist, &old).with_context(|| {
+                anyhow!(
+                    "failed in moving existing dist out of the way, dist={} to={}",
+                    self.dist.display(),
+                    old.display()
+                )
+            })?;
+            Some(old)
+        } else {
+            None
+        };
+
+        if let Err(e) = fs::rename(&self.staging, &self.dist) {
+            // put the older dist back, we should never leave the user without a dist
+            if let Some(old) = &old {
+                let _ = fs::rename(old, &self.dist);
+            }
+            return Err(e).with_context(|| {
+                anyhow!(
+                    "failed in moving staging directory to dist, staging={} dist={}",
+                    self.staging.display(),
+                    self.dist.display()
+                )
+            });
+        }
+        self.committed = true;
+        info!("dist written, path={}", self.dist.display());
+
+        if let Some(old) = old {
+            fs::remove_dir_all(&old).with_context(|| {
+                anyhow!("failed in removing the older dist, path={}", old.display())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagingDist {
+    fn drop(&mut self) {
+        if self.committed || !self.staging.exists() {
+            return;
+        }
+        info!("removing staging directory, path={}", self.staging.display());
+        if let Err(e) = fs::remove_dir_all(&self.staging) {
+            warn!(
+                "failed in removing staging directory, path={} error={}",
+                self.staging.display(),
+                e
+            );
+        }
+    }
+}
+
+fn sibling_path(dist: &PathBuf, kind: &str) -> Result<PathBuf> {
+    let (parent, prefix) = sibling_prefix(dist, kind)?;
+    Ok(parent.join(format!("{}{}", prefix, std::process::id())))
+}
+
+// siblings are named `.<dist>.yarp-<kind>-<pid>`, the pid is of the process which made them
+fn sibling_prefix(dist: &PathBuf, kind: &str) -> Result<(PathBuf, String)> {
+    let parent = dist.parent().ok_or_else(|| {
+        anyhow!("could not find parent directory of dist, path={}", dist.display())
+    })?;
+    let name = dist
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("could not find directory name of dist, path={}", dist.display()))?;
+    Ok((parent.to_path_buf(), format!(".{}.yarp-{}-", name, kind)))
+}
+
+// staging directories of exports to the same dist whose process is no longer running
+fn stale_staging_dirs(dist: &PathBuf) -> Result<Vec<PathBuf>> {
+    let (parent, prefix) = sibling_prefix(dist, "staging")?;
+    if !parent.exists() {
+        return Ok(Vec::new());
+    }
+    let mut stale = Vec::new();
+    for entry in fs::read_dir(&parent)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let pid = match name.to_str().and_then(|n| n.strip_prefix(&prefix)) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        match pid.parse::<u32>() {
+            Ok(pid) if is_alive(pid) => {}
+            _ => stale.push(entry.path()),
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+// /proc on linux, `kill -0` everywhere else, a process we can't check is assumed to be alive
+fn is_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    if Path::new("/proc/self").exists() {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    match Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) => status.success(),
+        Err(_) => true,
+    }
+}
+
+fn install_interrupt_cleanup(staging: &Path) {
+    let staging = staging.to_path_buf();
+    let res = ctrlc::set_handler(move || {
+        eprintln!("interrupted, removing staging directory {}", staging.display());
+        let _ = fs::remove_dir_all(&staging);
+        std::process::exit(130);
+    });
+    if let Err(e) = res {
+        warn!("failed in installing Ctrl-C handler, staging directory would not be cleaned on interrupt: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::StagingDist;
+
+    #[test]
+    fn test_commit_replaces_existing_dist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("old.txt"), "old").unwrap();
+
+        let staging = StagingDist::new(&dist).unwrap();
+        fs::write(staging.path().join("new.txt"), "new").unwrap();
+        let staging_path = staging.path().clone();
+        staging.commit(true).unwrap();
+
+        assert!(dist.join("new.txt").exists());
+        assert!(!dist.join("old.txt").exists());
+        assert!(!staging_path.exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_commit_without_force_keeps_existing_dist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("old.txt"), "old").unwrap();
+
+        let staging = StagingDist::new(&dist).unwrap();
+        let staging_path = staging.path().clone();
+        assert!(staging.commit(false).is_err());
+
+        assert!(dist.join("old.txt").exists());
+        assert!(!staging_path.exists());
+    }
+
+    #[test]
+    fn test_new_removes_stale_staging() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        // left by a killed export, no process has this pid
+        let stale = tmp.path().join(".dist.yarp-staging-999999999");
+        fs::create_dir_all(&stale).unwrap();
+        fs::write(stale.join("half-done.so"), "").unwrap();
+        // init is always running, its staging directory is not ours to remove
+        let running = tmp.path().join(".dist.yarp-staging-1");
+        fs::create_dir_all(&running).unwrap();
+
+        let staging = StagingDist::new(&dist).unwrap();
+        assert!(!stale.exists());
+        assert!(running.exists());
+        assert!(staging.path().exists());
+    }
+
+    #[test]
+    fn test_drop_removes_staging() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+
+        let staging = StagingDist::new(&dist).unwrap();
+        fs::write(staging.path().join("half-done.so"), "").unwrap();
+        let staging_path = staging.path().clone();
+        drop(staging);
+
+        assert!(!staging_path.exists());
+        assert!(!dist.exists());
+    }
+}
@@ -0,0 +1,254 @@
+// checks for external tools, run before the (long) graph build
+// patching shells out to patchelf/install_name_tool/codesign and search shells out to ldconfig/ldd
+// without this, a missing tool is only found after minutes of gathering
+
+use std::{io::ErrorKind, process::Command};
+
+use anyhow::{Error, Result, bail};
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+
+use crate::error::{Failures, YarpError};
+
+#[derive(Debug, Clone)]
+pub struct Tool {
+    // the program we execute
+    pub program: &'static str,
+
+    // args which make the tool print its version, the tool is only checked for existence if empty
+    pub version_args: &'static [&'static str],
+
+    // the minimum (major, minor) version we support
+    pub minimum: Option<(u32, u32)>,
+
+    // what we use the tool for, shown to the user
+    pub purpose: &'static str,
+
+    // the part of the export which needs the tool
+    pub stage: Stage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // gathering the graph, `export --plan` only needs these
+    Search,
+    // moving and patching the nodes in dist
+    Patch,
+}
+
+#[derive(Debug, Clone)]
+pub enum ToolStatus {
+    Ok { version: Option<String> },
+    Missing,
+    TooOld { version: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolCheck {
+    pub tool: Tool,
+    pub status: ToolStatus,
+}
+
+impl ToolCheck {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.status, ToolStatus::Ok { .. })
+    }
+
+    fn to_error(&self) -> Option<Error> {
+        let tool = self.tool.program.to_string();
+        match &self.status {
+            ToolStatus::Ok { .. } => None,
+            ToolStatus::Missing => Some(Error::new(YarpError::ToolMissing { tool })),
+            ToolStatus::TooOld { version } => Some(Error::new(YarpError::ToolTooOld {
+                tool,
+                version: version.clone(),
+                minimum: self
+                    .tool
+                    .minimum
+                    .map(|(major, minor)| format!("{}.{}", major, minor))
+                    .unwrap_or_default(),
+            })),
+        }
+    }
+}
+
+pub fn required_tools(os: &str) -> Vec<Tool> {
+    match os {
+        "linux" => vec![
+            Tool {
+                program: "patchelf",
+                version_args: &["--version"],
+                // `--add-rpath` was added in 0.14
+                minimum: Some((0, 14)),
+                purpose: "patching ELF binaries",
+                stage: Stage::Patch,
+            },
+            Tool {
+                program: "/sbin/ldconfig",
+                version_args: &["--version"],
+                minimum: None,
+                purpose: "searching the ld.so cache",
+                stage: Stage::Search,
+            },
+            Tool {
+                program: "ldd",
+                version_args: &["--version"],
+                minimum: None,
+                purpose: "fallback library search",
+                stage: Stage::Search,
+            },
+        ],
+        "macos" => vec![
+            Tool {
+                program: "install_name_tool",
+                version_args: &[],
+                minimum: None,
+                purpose: "patching Mach-O binaries",
+                stage: Stage::Patch,
+            },
+            Tool {
+                program: "codesign",
+                version_args: &[],
+                minimum: None,
+                purpose: "re-signing patched Mach-O binaries",
+                stage: Stage::Patch,
+            },
+        ],
+        _ => Vec::new(),
+    }
+}
+
+pub fn check_tools(os: &str) -> Vec<ToolCheck> {
+    required_tools(os)
+        .into_iter()
+        .map(|tool| {
+            let status = check_tool(&tool);
+            ToolCheck { tool, status }
+        })
+        .collect()
+}
+
+/// fail fast if any tool needed on `os` is missing or too old
+pub fn preflight(os: &str) -> Result<()> {
+    preflight_stages(os, &[Stage::Search, Stage::Patch])
+}
+
+/// like [`preflight`], but only for the tools needed to gather the graph
+pub fn preflight_search(os: &str) -> Result<()> {
+    preflight_stages(os, &[Stage::Search])
+}
+
+fn preflight_stages(os: &str, stages: &[Stage]) -> Result<()> {
+    info!("preflight: checking external tools for os={}", os);
+    let errors: Vec<Error> = check_tools(os)
+        .iter()
+        .filter(|c| stages.contains(&c.tool.stage))
+        .filter_map(|c| c.to_error())
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(Error::new(Failures {
+        context: "preflight failed, required tools are not usable".to_string(),
+        errors,
+    }))
+}
+
+fn check_tool(tool: &Tool) -> ToolStatus {
+    // tools without a version flag print their usage and exit with a failure, we only care that they could be executed
+    let output = Command::new(tool.program)
+        .args(tool.version_args)
+        .env("LANG", "C")
+        .env("LC_ALL", "C")
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => return ToolStatus::Missing,
+        // permission errors and friends, the tool is as good as missing
+        Err(_) => return ToolStatus::Missing,
+    };
+    if tool.version_args.is_empty() {
+        return ToolStatus::Ok { version: None };
+    }
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let version = parse_version(&text);
+    match (tool.minimum, version) {
+        (Some(minimum), Some((version, parsed))) => {
+            if parsed < minimum {
+                ToolStatus::TooOld { version }
+            } else {
+                ToolStatus::Ok {
+                    version: Some(version),
+                }
+            }
+        }
+        (_, version) => ToolStatus::Ok {
+            version: version.map(|(v, _)| v),
+        },
+    }
+}
+
+lazy_static! {
+    static ref VERSION_RE: Regex =
+        Regex::new(r"(\d+)\.(\d+)(\.\d+)?").expect("failed to compile regex for tool versions");
+}
+
+// find the first version looking string in the output of a tool
+fn parse_version(output: &str) -> Option<(String, (u32, u32))> {
+    let caps = VERSION_RE.captures(output)?;
+    let major = caps.get(1)?.as_str().parse().ok()?;
+    let minor = caps.get(2)?.as_str().parse().ok()?;
+    Some((caps.get(0)?.as_str().to_string(), (major, minor)))
+}
+
+pub fn ensure_all_ok(checks: &Vec<ToolCheck>) -> Result<()> {
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter(|c| !c.is_ok())
+        .map(|c| c.tool.program)
+        .collect();
+    if !failed.is_empty() {
+        bail!("required tools are not usable: {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Stage, parse_version, required_tools};
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("patchelf 0.14.3\n"),
+            Some(("0.14.3".to_string(), (0, 14)))
+        );
+        assert_eq!(
+            parse_version("ldd (Ubuntu GLIBC 2.35-0ubuntu3.8) 2.35\nCopyright (C) 2022"),
+            Some(("2.35".to_string(), (2, 35)))
+        );
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[test]
+    fn test_required_tools() {
+        let linux: Vec<&str> = required_tools("linux").iter().map(|t| t.program).collect();
+        assert!(linux.contains(&"patchelf"));
+        let macos: Vec<&str> = required_tools("macos").iter().map(|t| t.program).collect();
+        assert!(macos.contains(&"install_name_tool"));
+        assert!(macos.contains(&"codesign"));
+        assert!(required_tools("windows").is_empty());
+        // patching tools are not needed for `export --plan`
+        let search: Vec<&str> = required_tools("linux")
+            .iter()
+            .filter(|t| t.stage == Stage::Search)
+            .map(|t| t.program)
+            .collect();
+        assert_eq!(search, vec!["/sbin/ldconfig", "ldd"]);
+    }
+}
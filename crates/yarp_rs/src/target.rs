@@ -0,0 +1,143 @@
+// the platform and architecture binaries are analysed for
+// `yarp.discover` records the target in the manifest, so a macos environment snapshot can be inspected on a linux host
+// manifests without it (older ones) are analysed for the host
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, anyhow};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Os {
+    Linux,
+    #[serde(alias = "darwin")]
+    Macos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Arch {
+    #[serde(rename = "x86_64")]
+    X86_64,
+    #[serde(rename = "aarch64", alias = "arm64")]
+    Aarch64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Target {
+    pub os: Os,
+    pub arch: Arch,
+}
+
+impl Target {
+    pub fn host() -> Result<Target> {
+        let os = std::env::consts::OS;
+        let arch = std::env::consts::ARCH;
+        Ok(Target {
+            os: os
+                .parse()
+                .map_err(|e| anyhow!("unsupported host platform: {}", e))?,
+            arch: arch
+                .parse()
+                .map_err(|e| anyhow!("unsupported host architecture: {}", e))?,
+        })
+    }
+
+    /// `--target` wins over `target` in the manifest, the host is used if neither is given
+    pub fn resolve(flag: Option<Target>, manifest: Option<Target>) -> Result<Target> {
+        match flag.or(manifest) {
+            Some(target) => Ok(target),
+            None => Target::host(),
+        }
+    }
+}
+
+impl FromStr for Os {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(Os::Linux),
+            "macos" | "darwin" => Ok(Os::Macos),
+            _ => Err(format!("{}, only linux and macos are supported", s)),
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" | "arm64" => Ok(Arch::Aarch64),
+            _ => Err(format!("{}, only x86_64 and aarch64 are supported", s)),
+        }
+    }
+}
+
+/// `<os>-<arch>`, like `macos-aarch64` or `linux-x86_64`
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (os, arch) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid target {}, expected <os>-<arch> like macos-aarch64", s))?;
+        Ok(Target {
+            os: os.parse()?,
+            arch: arch.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Os {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Os::Linux => write!(f, "linux"),
+            Os::Macos => write!(f, "macos"),
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arch::X86_64 => write!(f, "x86_64"),
+            Arch::Aarch64 => write!(f, "aarch64"),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.os, self.arch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arch, Os, Target};
+
+    #[test]
+    fn test_parse_target() {
+        let target: Target = "darwin-arm64".parse().unwrap();
+        assert_eq!(
+            target,
+            Target {
+                os: Os::Macos,
+                arch: Arch::Aarch64
+            }
+        );
+        assert_eq!(target.to_string(), "macos-aarch64");
+        assert_eq!("linux-x86_64".parse::<Target>().unwrap().to_string(), "linux-x86_64");
+        assert!("windows-x86_64".parse::<Target>().is_err());
+        assert!("linux".parse::<Target>().is_err());
+
+        // the manifest uses the same names
+        let target: Target = serde_json::from_str(r#"{"os": "darwin", "arch": "arm64"}"#).unwrap();
+        assert_eq!(target.os, Os::Macos);
+        assert_eq!(serde_json::to_string(&target).unwrap(), r#"{"os":"macos","arch":"aarch64"}"#);
+    }
+}
@@ -1,6 +1,7 @@
 from functools import partial
 import json
 import os
+import sys
 import atexit
 from typing import Optional
 from yarp.discover.all_libs import get_libs
@@ -8,6 +9,7 @@ from yarp.discover.callbacks import cffi_dlopen_callback, ctypes_cdll_callback
 from yarp.discover.imports import add_import_callback, register_import_watcher
 from yarp.discover.python_props import get_python_props
 from yarp.discover.monkeypatch import kwarg_else_arg, try_monkey_patch
+from yarp.discover.plat import get_target
 from yarp.discover.types import *
 
 DUMP_LOC_ENV_VAR = "YARP_JSON"
@@ -80,6 +82,8 @@ def exit_handler(prefixes_to_skip: list[str]):
         python=get_python_props(),
         skip=Skip(prefixes=prefixes_to_skip, libs=[]),
         env={str(k): str(v) for k, v in os.environ.items()},
+        imports=sorted({name.split(".")[0] for name in list(sys.modules)}),
+        target=get_target(),
     )
     with open(dump_loc, "w") as f:
         json.dump(payload.to_dict(), f)
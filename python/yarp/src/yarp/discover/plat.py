@@ -1,5 +1,8 @@
+import platform
 import sys
-from typing import Literal
+from typing import Literal, Optional
+
+from yarp.discover.types import Target
 
 
 Platform = Literal["linux", "darwin", "unknown"]
@@ -10,4 +13,15 @@ def get_platform():
     elif sys.platform == "darwin":
         return "mac"
     else:
-        return "unknown"
\ No newline at end of file
+        return "unknown"
+
+
+def get_target() -> Optional[Target]:
+    """the target yarp analyses binaries for, `None` if yarp does not support this platform"""
+    os = {"linux": "linux", "mac": "macos"}.get(get_platform())
+    arch = {"x86_64": "x86_64", "amd64": "x86_64", "aarch64": "aarch64", "arm64": "aarch64"}.get(
+        platform.machine().lower()
+    )
+    if os is None or arch is None:
+        return None
+    return Target(os=os, arch=arch)
@@ -1,9 +1,12 @@
 
-from dataclasses import dataclass
-from typing import Any, Literal
+from dataclasses import dataclass, field
+from typing import Any, Literal, Optional
 
 LoadKind = Literal["extension", "dlopen"]
 
+# must match `MANIFEST_VERSION` in yarp_rs, `yarp manifest schema` prints the JSON Schema of this version
+MANIFEST_VERSION = 1
+
 @dataclass(frozen=True)
 class LocalLoad:
     kind: LoadKind
@@ -84,6 +87,17 @@ class Skip:
 
 
 
+@dataclass(frozen=True)
+class Target:
+    # "linux" or "macos", `get_target()` returns None on any other platform
+    os: str
+    # "x86_64" or "aarch64", `get_target()` returns None on any other architecture
+    arch: str
+
+    def to_dict(self) -> dict[str, Any]:
+        return {"os": self.os, "arch": self.arch}
+
+
 @dataclass(frozen=True)
 class YarpDiscovery:
     loads: list[Load]
@@ -91,12 +105,19 @@ class YarpDiscovery:
     python: Python
     skip: Skip
     env: dict[str, str]
+    # top-level modules imported during discovery, used for pruning site-packages
+    imports: list[str] = field(default_factory=list)
+    # platform of this python, lets yarp analyse the manifest on another host
+    target: Optional[Target] = None
     
     def to_dict(self) -> dict[str, Any]:
         return {
+            "version": MANIFEST_VERSION,
             "loads": [load.to_dict() for load in self.loads],
             "libs": [lib.to_dict() for lib in self.libs],
             "python": self.python.to_dict(),
             "skip": self.skip.to_dict(),
             "env": self.env,
+            "imports": self.imports,
+            **({"target": self.target.to_dict()} if self.target else {}),
         }
\ No newline at end of file
@@ -1,4 +1,5 @@
 from yarp.discover.types import (
+    MANIFEST_VERSION,
     Lib,
     Skip,
     YarpDiscovery,
@@ -43,6 +44,8 @@ def test_yarp_discovery_serialization():
     serialized = discovery.to_dict()
 
     # Verify the serialized output
+    assert serialized["version"] == MANIFEST_VERSION
+    assert serialized["imports"] == []
     assert len(serialized["loads"]) == 2
     assert serialized["loads"][0]["path"] == "/path/to/load1"
     assert serialized["loads"][1]["path"] == "/path/to/load2"